@@ -1,3 +1,5 @@
+use std::path::{Path, PathBuf};
+
 use crate::{proofs::RULE_PROOF_KEYWORD, *};
 
 fn desugar_datatype(name: Symbol, variants: Vec<Variant>) -> Vec<NCommand> {
@@ -14,6 +16,7 @@ fn desugar_datatype(name: Symbol, variants: Vec<Variant>) -> Vec<NCommand> {
                 merge_action: vec![],
                 default: None,
                 cost: variant.cost,
+                unextractable: variant.unextractable,
             })
         }))
         .collect()
@@ -315,12 +318,120 @@ fn add_semi_naive_rule(desugar: &mut Desugar, rule: Rule) -> Option<Rule> {
 }
 
 pub struct Desugar {
-    next_fresh: usize,
-    next_command_id: usize,
+    pub(crate) next_fresh: usize,
+    pub(crate) next_command_id: usize,
     pub(crate) parser: ast::parse::ProgramParser,
     pub(crate) action_parser: ast::parse::ActionParser,
     // TODO fix getting fresh names using modules
     pub(crate) number_underscores: usize,
+    /// The directories searched for included files that are not found next
+    /// to the file including them.
+    pub(crate) include_path: Vec<PathBuf>,
+    /// The canonical paths of the files included so far, with the module
+    /// each was included into, which are not included there again.
+    pub(crate) included: HashSet<(PathBuf, Symbol)>,
+    /// The canonical paths of the files being included, innermost last.
+    pub(crate) include_stack: Vec<PathBuf>,
+    /// The modules being desugared, innermost last.
+    pub(crate) modules: Vec<ModuleScope>,
+    /// The qualified names that modules do not export, with the qualified
+    /// name of the module each belongs to.
+    pub(crate) hidden: HashMap<Symbol, Symbol>,
+    /// The qualified names of the sorts, functions, globals and rulesets
+    /// declared so far.
+    pub(crate) declared: HashSet<Symbol>,
+    /// The macros defined so far.
+    pub(crate) macros: HashMap<Symbol, Macro>,
+    /// The macros being expanded, innermost last.
+    pub(crate) expanding: Vec<Symbol>,
+}
+
+/// A module being desugared.
+#[derive(Clone, Debug)]
+pub(crate) struct ModuleScope {
+    name: Symbol,
+    /// The names declared in the module so far, unqualified.
+    names: HashSet<Symbol>,
+    /// The names exported by the module's `export` commands, if it has any.
+    exports: Option<Vec<Symbol>>,
+}
+
+/// A macro defined by `define-macro`.
+#[derive(Clone, Debug)]
+pub(crate) struct Macro {
+    params: Vec<Symbol>,
+    body: Vec<Command>,
+    /// The names the body declares or binds with `let` in a rule, other than
+    /// the parameters, which are given fresh names in each expansion so that
+    /// they cannot clash with the names where the macro is invoked.
+    locals: Vec<Symbol>,
+    /// The other variables of the body's rules. Each is given a fresh name
+    /// too, unless it names a global or function where the macro is invoked,
+    /// which it then refers to as in any other rule.
+    variables: Vec<Symbol>,
+}
+
+impl Macro {
+    fn new(params: Vec<Symbol>, body: Vec<Command>) -> Self {
+        // the globals, functions, sorts and rulesets that the body declares,
+        // and the variables of its rules
+        let mut names = vec![];
+        let mut rule_vars = vec![];
+        for command in &body {
+            names.extend(command.declared_names());
+            let rule = match command {
+                Command::Rule { rule, .. } => rule.clone(),
+                Command::Rewrite(_, rewrite) | Command::BiRewrite(_, rewrite) => Rule {
+                    head: vec![],
+                    body: (rewrite.conditions.iter().cloned())
+                        .chain([Fact::Eq(vec![rewrite.lhs.clone(), rewrite.rhs.clone()])])
+                        .collect(),
+                },
+                _ => continue,
+            };
+            names.extend(rule.head.iter().filter_map(|action| match action {
+                Action::Let(name, _) => Some(*name),
+                _ => None,
+            }));
+            rule.map_exprs(&mut |e| {
+                let mut var = |e: &Expr| {
+                    if let Expr::Var(v) = e {
+                        rule_vars.push(*v)
+                    }
+                };
+                e.walk(&mut var, &mut |_| {});
+                e.clone()
+            });
+        }
+
+        let mut locals = vec![];
+        for name in names {
+            if !params.contains(&name) && !locals.contains(&name) {
+                locals.push(name);
+            }
+        }
+        let mut variables = vec![];
+        for var in rule_vars {
+            if !params.contains(&var) && !locals.contains(&var) && !variables.contains(&var) {
+                variables.push(var);
+            }
+        }
+        Macro {
+            params,
+            body,
+            locals,
+            variables,
+        }
+    }
+
+    /// The `define-macro` command defining this macro as `name`.
+    pub(crate) fn to_command(&self, name: Symbol) -> Command {
+        Command::DefineMacro {
+            name,
+            params: self.params.clone(),
+            body: self.body.clone(),
+        }
+    }
 }
 
 impl Default for Desugar {
@@ -332,6 +443,14 @@ impl Default for Desugar {
             parser: ast::parse::ProgramParser::new(),
             action_parser: ast::parse::ActionParser::new(),
             number_underscores: 3,
+            include_path: vec![],
+            included: Default::default(),
+            include_stack: vec![],
+            modules: vec![],
+            hidden: Default::default(),
+            declared: Default::default(),
+            macros: Default::default(),
+            expanding: vec![],
         }
     }
 }
@@ -369,6 +488,7 @@ pub(crate) fn desugar_calc(
                     limit: 1000000,
                     until: Some(vec![Fact::Eq(vec![expr1.clone(), expr2.clone()])]),
                 }),
+                None,
                 desugar,
                 false,
                 seminaive,
@@ -392,10 +512,12 @@ pub(crate) fn rewrite_name(rewrite: &Rewrite) -> String {
 
 pub(crate) fn desugar_command(
     command: Command,
+    span: Option<&Span>,
     desugar: &mut Desugar,
     get_all_proofs: bool,
     seminaive: bool,
 ) -> Result<Vec<NormCommand>, Error> {
+    desugar.declared.extend(command.declared_names());
     let res = match command {
         Command::SetOption { name, value } => {
             vec![NCommand::SetOption { name, value }]
@@ -412,14 +534,45 @@ pub(crate) fn desugar_command(
             desugar_birewrite(ruleset, rewrite_name(&rewrite).into(), &rewrite, desugar)
         }
         Command::Include(file) => {
-            let s = std::fs::read_to_string(&file)
-                .unwrap_or_else(|_| panic!("Failed to read file {file}"));
-            return desugar_commands(
-                desugar.parse_program(&s)?,
-                desugar,
-                get_all_proofs,
-                seminaive,
-            );
+            return desugar_include(&file, None, span, desugar, get_all_proofs, seminaive)
+        }
+        Command::Import { file, name } => {
+            return desugar_include(&file, Some(name), span, desugar, get_all_proofs, seminaive)
+        }
+        Command::Module { name, body } => {
+            return desugar_module(name, desugar, |desugar| {
+                let mut res = vec![];
+                for (body_span, command) in body {
+                    let span = body_span.as_ref().or(span);
+                    let desugared =
+                        desugar_resolved(vec![command], span, desugar, get_all_proofs, seminaive);
+                    res.extend(desugared.map_err(|e| e.with_span(span))?);
+                }
+                Ok(res)
+            })
+        }
+        Command::DefineMacro { name, params, body } => {
+            desugar.macros.insert(name, Macro::new(params, body));
+            vec![]
+        }
+        Command::Action(Action::Expr(Expr::Call(name, args)))
+            if desugar.macros.contains_key(&name) =>
+        {
+            if desugar.expanding.contains(&name) {
+                return Err(Error::MacroCycle(name));
+            }
+            let expansion = desugar.expand_macro(name, args)?;
+            desugar.expanding.push(name);
+            let res = desugar_resolved(expansion, span, desugar, get_all_proofs, seminaive);
+            desugar.expanding.pop();
+            return res;
+        }
+        Command::Export(names) => {
+            // exporting from the top level has no effect
+            if let Some(module) = desugar.modules.last_mut() {
+                module.exports.get_or_insert_with(Vec::new).extend(names);
+            }
+            vec![]
         }
         Command::Rule {
             ruleset,
@@ -493,7 +646,7 @@ pub(crate) fn desugar_command(
         Command::RunSchedule(sched) => {
             vec![NCommand::RunSchedule(desugar_schedule(desugar, &sched))]
         }
-        Command::Extract { variants, e } => {
+        Command::Extract { variants, mode, e } => {
             let fresh = desugar.get_fresh();
             flatten_actions(&vec![Action::Let(fresh, e)], desugar)
                 .into_iter()
@@ -501,6 +654,7 @@ pub(crate) fn desugar_command(
                 .chain(
                     vec![NCommand::Extract {
                         variants,
+                        mode,
                         var: fresh,
                     }]
                     .into_iter(),
@@ -533,6 +687,7 @@ pub(crate) fn desugar_command(
                             name: "".into(),
                             rule: dummyrule,
                         },
+                        span,
                         desugar,
                         get_all_proofs,
                         seminaive,
@@ -560,6 +715,7 @@ pub(crate) fn desugar_command(
                 // extract the proof
                 res.push(NCommand::Extract {
                     variants: 0,
+                    mode: ExtractMode::Tree,
                     var: proofvar,
                 });
             }
@@ -569,6 +725,32 @@ pub(crate) fn desugar_command(
         Command::Print(symbol, size) => vec![NCommand::Print(symbol, size)],
         Command::PrintSize(symbol) => vec![NCommand::PrintSize(symbol)],
         Command::Output { file, exprs } => vec![NCommand::Output { file, exprs }],
+        Command::OutputTable { name, file } => vec![NCommand::OutputTable { name, file }],
+        Command::Save(file) => vec![NCommand::Save(file)],
+        Command::Load(file) => vec![NCommand::Load(file)],
+        Command::ExportJson(file) => vec![NCommand::ExportJson(file)],
+        Command::ExportDot {
+            file,
+            functions,
+            root,
+        } => {
+            let mut res = vec![];
+            let root = root.map(|root| {
+                let fresh = desugar.get_fresh();
+                res.extend(
+                    flatten_actions(&vec![Action::Let(fresh, root)], desugar)
+                        .into_iter()
+                        .map(NCommand::NormAction),
+                );
+                fresh
+            });
+            res.push(NCommand::ExportDot {
+                file,
+                functions,
+                root,
+            });
+            res
+        }
         Command::Push(num) => {
             vec![NCommand::Push(num)]
         }
@@ -576,7 +758,7 @@ pub(crate) fn desugar_command(
             vec![NCommand::Pop(num)]
         }
         Command::Fail(cmd) => {
-            let mut desugared = desugar_command(*cmd, desugar, false, seminaive)?;
+            let mut desugared = desugar_command(*cmd, span, desugar, false, seminaive)?;
 
             let last = desugared.pop().unwrap();
             desugared.push(NormCommand {
@@ -585,8 +767,18 @@ pub(crate) fn desugar_command(
             });
             return Ok(desugared);
         }
-        Command::Input { name, file } => {
-            vec![NCommand::Input { name, file }]
+        Command::Input {
+            name,
+            file,
+            format,
+            header,
+        } => {
+            vec![NCommand::Input {
+                name,
+                file,
+                format,
+                header,
+            }]
         }
     };
 
@@ -595,21 +787,135 @@ pub(crate) fn desugar_command(
         .map(|c| NormCommand {
             metadata: Metadata {
                 id: desugar.get_new_id(),
+                span: span.cloned(),
             },
             command: c,
         })
         .collect())
 }
 
+/// Desugar `commands`, which came from the source at `span`, after
+/// qualifying their names.
+fn desugar_resolved(
+    commands: Vec<Command>,
+    span: Option<&Span>,
+    desugar: &mut Desugar,
+    get_all_proofs: bool,
+    seminaive: bool,
+) -> Result<Vec<NormCommand>, Error> {
+    let mut res = vec![];
+    for command in commands {
+        let command = desugar.resolve_names(command)?;
+        res.extend(desugar_command(
+            command,
+            span,
+            desugar,
+            get_all_proofs,
+            seminaive,
+        )?);
+    }
+    Ok(res)
+}
+
+/// Desugar the file `file`, included by the source at `span`, or imported
+/// as the module `module`.
+///
+/// A file is included into each module at most once, but may be imported
+/// any number of times.
+fn desugar_include(
+    file: &str,
+    module: Option<Symbol>,
+    span: Option<&Span>,
+    desugar: &mut Desugar,
+    get_all_proofs: bool,
+    seminaive: bool,
+) -> Result<Vec<NormCommand>, Error> {
+    let path = desugar.resolve_include(file, span);
+    let s = std::fs::read_to_string(&path).map_err(|e| Error::IoError(path.clone(), e))?;
+    let canonical = path
+        .canonicalize()
+        .map_err(|e| Error::IoError(path.clone(), e))?;
+    let including = span
+        .and_then(|span| span.file.name.as_ref())
+        .and_then(|name| Path::new(name).canonicalize().ok());
+    if desugar.include_stack.contains(&canonical) || including == Some(canonical.clone()) {
+        return Err(Error::IncludeCycle(path));
+    }
+    let included = (canonical.clone(), desugar.module_path());
+    if module.is_none() && desugar.included.contains(&included) {
+        return Ok(vec![]);
+    }
+
+    let filename = path.to_string_lossy().into_owned();
+    let desugar_file = |desugar: &mut Desugar| -> Result<Vec<NormCommand>, Error> {
+        let mut res = vec![];
+        for (span, command) in desugar.parse_spanned_program(Some(filename), &s)? {
+            let desugared = desugar.resolve_names(command).and_then(|command| {
+                desugar_command(command, Some(&span), desugar, get_all_proofs, seminaive)
+            });
+            res.extend(desugared.map_err(|e| e.with_span(Some(&span)))?);
+        }
+        Ok(res)
+    };
+    desugar.include_stack.push(canonical);
+    let res = match module {
+        Some(name) => desugar_module(name, desugar, desugar_file),
+        None => desugar_file(desugar),
+    };
+    desugar.include_stack.pop();
+    if res.is_ok() && module.is_none() {
+        desugar.included.insert(included);
+    }
+    res
+}
+
+/// Desugar the body of the module `name` with `desugar_body`, then make the
+/// names it exports visible outside it.
+fn desugar_module(
+    name: Symbol,
+    desugar: &mut Desugar,
+    desugar_body: impl FnOnce(&mut Desugar) -> Result<Vec<NormCommand>, Error>,
+) -> Result<Vec<NormCommand>, Error> {
+    let path = desugar.qualify_with_path(name);
+    desugar.modules.push(ModuleScope {
+        name,
+        names: Default::default(),
+        exports: None,
+    });
+    let res = desugar_body(desugar);
+    let module = desugar.modules.pop().unwrap();
+    let res = res?;
+
+    if let Some(exports) = &module.exports {
+        if let Some(name) = exports.iter().find(|&name| !module.names.contains(name)) {
+            return Err(TypeError::Unbound(*name).into());
+        }
+    }
+    for declared in &module.names {
+        let exported = (module.exports.as_ref()).map_or(true, |exports| exports.contains(declared));
+        if exported {
+            if let Some(parent) = desugar.modules.last_mut() {
+                parent.names.insert(format!("{name}.{declared}").into());
+            }
+        } else {
+            desugar
+                .hidden
+                .insert(format!("{path}.{declared}").into(), path);
+        }
+    }
+    Ok(res)
+}
+
 pub(crate) fn desugar_commands(
     program: Vec<Command>,
+    span: Option<&Span>,
     desugar: &mut Desugar,
     get_all_proofs: bool,
     seminaive: bool,
 ) -> Result<Vec<NormCommand>, Error> {
     let mut res = vec![];
     for command in program {
-        let desugared = desugar_command(command, desugar, get_all_proofs, seminaive)?;
+        let desugared = desugar_command(command, span, desugar, get_all_proofs, seminaive)?;
         res.extend(desugared);
     }
     Ok(res)
@@ -623,6 +929,14 @@ impl Clone for Desugar {
             parser: ast::parse::ProgramParser::new(),
             action_parser: ast::parse::ActionParser::new(),
             number_underscores: self.number_underscores,
+            include_path: self.include_path.clone(),
+            included: self.included.clone(),
+            include_stack: self.include_stack.clone(),
+            modules: self.modules.clone(),
+            hidden: self.hidden.clone(),
+            declared: self.declared.clone(),
+            macros: self.macros.clone(),
+            expanding: self.expanding.clone(),
         }
     }
 }
@@ -638,19 +952,123 @@ impl Desugar {
         .into()
     }
 
+    /// The path of `file`, included by the source at `span`. It is looked
+    /// for next to the including file, then in each directory of the include
+    /// path, and then in the working directory.
+    fn resolve_include(&self, file: &str, span: Option<&Span>) -> PathBuf {
+        let including_dir = span
+            .and_then(|span| span.file.name.as_ref())
+            .and_then(|name| Path::new(name).parent());
+        (including_dir.into_iter())
+            .chain(self.include_path.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(file))
+            .find(|path| path.is_file())
+            .unwrap_or_else(|| file.into())
+    }
+
+    /// The qualified name of the module being desugared, or the empty
+    /// string at the top level.
+    fn module_path(&self) -> Symbol {
+        let names: Vec<_> = self.modules.iter().map(|m| m.name.to_string()).collect();
+        names.join(".").into()
+    }
+
+    /// `name` qualified with the names of the modules being desugared.
+    fn qualify_with_path(&self, name: Symbol) -> Symbol {
+        match self.module_path() {
+            path if path == "".into() => name,
+            path => format!("{path}.{name}").into(),
+        }
+    }
+
+    /// Qualify the names in `command` that refer to declarations of the
+    /// modules being desugared, including those `command` itself declares.
+    /// Names that a module does not export cannot be referred to outside it.
+    pub(crate) fn resolve_names(&mut self, command: Command) -> Result<Command, Error> {
+        if self.modules.is_empty() && self.hidden.is_empty() {
+            return Ok(command);
+        }
+        if let Some(module) = self.modules.last_mut() {
+            module.names.extend(command.declared_names());
+        }
+
+        let path = self.module_path().to_string();
+        let mut error = None;
+        let command = command.map_symbols(&mut |name| {
+            let qualified = self.qualify(name);
+            if let Some(&owner) = self.hidden.get(&qualified) {
+                let owner = owner.to_string();
+                if path != owner && !path.starts_with(&format!("{owner}.")) {
+                    error.get_or_insert(Error::NotExported(qualified, owner.into()));
+                }
+            }
+            qualified
+        });
+        match error {
+            Some(error) => Err(error),
+            None => Ok(command),
+        }
+    }
+
+    /// The name `name` stands for in the modules being desugared: qualified by
+    /// the innermost module declaring it, if any.
+    fn qualify(&self, name: Symbol) -> Symbol {
+        (0..self.modules.len())
+            .rev()
+            .find(|&i| self.modules[i].names.contains(&name))
+            .map_or(name, |i| {
+                let modules = self.modules[..=i].iter().map(|m| m.name.to_string());
+                let modules: Vec<_> = modules.collect();
+                format!("{}.{name}", modules.join(".")).into()
+            })
+    }
+
+    /// The commands that the macro `name` expands to with `args`.
+    fn expand_macro(&mut self, name: Symbol, args: Vec<Expr>) -> Result<Vec<Command>, Error> {
+        let mac = self.macros[&name].clone();
+        if args.len() != mac.params.len() {
+            return Err(TypeError::Arity {
+                expr: Expr::Call(name, args),
+                expected: mac.params.len(),
+            }
+            .into());
+        }
+
+        let mut renaming = HashMap::default();
+        for (param, arg) in mac.params.iter().zip(args) {
+            match arg {
+                Expr::Var(arg) => renaming.insert(*param, arg),
+                arg => return Err(Error::MacroArgument(name, arg)),
+            };
+        }
+        for local in &mac.locals {
+            renaming.insert(*local, self.get_fresh());
+        }
+        for &var in &mac.variables {
+            if !self.declared.contains(&self.qualify(var)) {
+                renaming.insert(var, self.get_fresh());
+            }
+        }
+        let rename = &mut |name: Symbol| renaming.get(&name).copied().unwrap_or(name);
+        Ok(mac.body.iter().map(|c| c.map_symbols(rename)).collect())
+    }
+
     pub fn get_new_id(&mut self) -> CommandId {
         let res = self.next_command_id;
         self.next_command_id += 1;
         res
     }
 
+    /// Desugar `program`, which came from the source at `span` if it was
+    /// parsed.
     pub(crate) fn desugar_program(
         &mut self,
         program: Vec<Command>,
+        span: Option<&Span>,
         get_all_proofs: bool,
         seminaive: bool,
     ) -> Result<Vec<NormCommand>, Error> {
-        let res = desugar_commands(program, self, get_all_proofs, seminaive)?;
+        let res = desugar_commands(program, span, self, get_all_proofs, seminaive)?;
         Ok(res)
     }
 
@@ -693,10 +1111,64 @@ impl Desugar {
     }
 
     pub fn parse_program(&self, input: &str) -> Result<Vec<Command>, Error> {
-        Ok(self
-            .parser
-            .parse(input)
-            .map_err(|e| e.map_token(|tok| tok.to_string()))?)
+        let parsed = self.parse_spanned_program(None, input)?;
+        Ok(parsed.into_iter().map(|(_, command)| command).collect())
+    }
+
+    /// Parse `input` as a single action.
+    pub(crate) fn parse_action(&self, input: &str) -> Result<Action, Error> {
+        // actions have no module bodies, so nothing points into the file
+        let file = Arc::new(SrcFile {
+            name: None,
+            contents: String::new(),
+        });
+        (self.action_parser.parse(&file, input)).map_err(|e| {
+            let e = e.map_token(|tok| tok.to_string());
+            Error::ParseError(e.map_error(|(_, _, message)| message))
+        })
+    }
+
+    /// Parse `input`, read from the file `filename` if there is one, into
+    /// commands along with their spans.
+    pub fn parse_spanned_program(
+        &self,
+        filename: Option<String>,
+        input: &str,
+    ) -> Result<Vec<(Span, Command)>, Error> {
+        let file = Arc::new(SrcFile {
+            name: filename,
+            contents: input.to_string(),
+        });
+        let span = |start, end| Span {
+            file: file.clone(),
+            start,
+            end,
+        };
+        let program = self.parser.parse(&file, input).map_err(|e| {
+            use lalrpop_util::ParseError::*;
+            let e = e.map_token(|tok| tok.to_string());
+            let (start, end) = match &e {
+                InvalidToken { location } | UnrecognizedEOF { location, .. } => {
+                    (*location, *location)
+                }
+                UnrecognizedToken {
+                    token: (start, _, end),
+                    ..
+                }
+                | ExtraToken {
+                    token: (start, _, end),
+                } => (*start, *end),
+                User {
+                    error: (start, end, _),
+                } => (*start, *end),
+            };
+            let e = e.map_error(|(_, _, message)| message);
+            Error::ParseError(e).with_span(Some(&span(start, end)))
+        })?;
+        Ok(program
+            .into_iter()
+            .map(|(start, command, end)| (span(start, end), command))
+            .collect())
     }
 
     pub fn declare(&mut self, name: Symbol, sort: Symbol) -> Vec<NCommand> {
@@ -712,6 +1184,7 @@ impl Desugar {
                 merge: None,
                 merge_action: vec![],
                 cost: Some(HIGH_COST),
+                unextractable: false,
             }),
             NCommand::NormAction(NormAction::Let(name, NormExpr::Call(fresh, vec![]))),
         ]
@@ -39,21 +39,94 @@ impl Display for Literal {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match &self {
             Literal::Int(i) => Display::fmt(i, f),
-            Literal::F64(n) => {
-                // need to display with decimal if there is none
-                let str = n.to_string();
-                if let Ok(_num) = str.parse::<i64>() {
-                    write!(f, "{}.0", str)
-                } else {
-                    write!(f, "{}", str)
-                }
-            }
-            Literal::String(s) => write!(f, "\"{}\"", s),
+            // `Debug` always writes a decimal point or an exponent, uses an
+            // exponent for very large and small numbers, and writes the
+            // shortest digits that read back as the same number
+            Literal::F64(n) => write!(f, "{:?}", n.0),
+            Literal::String(s) => write!(f, "{}", quote(s.as_str())),
             Literal::Unit => write!(f, "()"),
         }
     }
 }
 
+/// The integer written as `literal`, in decimal, or in hexadecimal or binary
+/// after a `0x` or `0b` prefix.
+pub(crate) fn parse_int(literal: &str) -> Result<i64, String> {
+    let (sign, digits) = match literal.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", literal),
+    };
+    let (radix, digits) = if let Some(digits) = digits.strip_prefix("0x") {
+        (16, digits)
+    } else if let Some(digits) = digits.strip_prefix("0b") {
+        (2, digits)
+    } else {
+        (10, digits)
+    };
+    i64::from_str_radix(&format!("{sign}{digits}"), radix)
+        .map_err(|e| format!("Invalid integer {literal}: {e}"))
+}
+
+/// `s` as a string literal, with the characters that need it escaped.
+pub(crate) fn quote(s: &str) -> String {
+    let mut res = String::with_capacity(s.len() + 2);
+    res.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\t' => res.push_str("\\t"),
+            '\r' => res.push_str("\\r"),
+            c if c.is_control() => res.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+/// The contents of the string literal `literal`, quotes included, with its
+/// escapes replaced by the characters they stand for. A doubled quote `""`
+/// also stands for a quote.
+pub(crate) fn unquote(literal: &str) -> Result<String, String> {
+    let mut res = String::with_capacity(literal.len());
+    let mut chars = literal[1..literal.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                // the lexer only lets a quote in as the second of a pair
+                chars.next();
+                res.push('"');
+            }
+            '\\' => res.push(match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some(c @ ('\\' | '"')) => c,
+                Some('u') => {
+                    let escape =
+                        (chars.as_str().strip_prefix('{')).and_then(|rest| rest.split_once('}'));
+                    let c = escape
+                        .and_then(|(code, _)| u32::from_str_radix(code, 16).ok())
+                        .and_then(char::from_u32);
+                    match (escape, c) {
+                        (Some((_, rest)), Some(c)) => {
+                            chars = rest.chars();
+                            c
+                        }
+                        _ => return Err(format!("Invalid unicode escape in {literal}")),
+                    }
+                }
+                Some(c) => return Err(format!("Unknown escape \\{c} in {literal}")),
+                None => return Err(format!("Unterminated escape in {literal}")),
+            }),
+            c => res.push(c),
+        }
+    }
+    Ok(res)
+}
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 pub enum Expr {
     Lit(Literal),
@@ -137,6 +210,15 @@ impl Expr {
         }
     }
 
+    /// Rename the variables and functions in the expression.
+    pub fn map_symbols(&self, f: &mut impl FnMut(Symbol) -> Symbol) -> Self {
+        self.map(&mut |expr| match expr {
+            Expr::Lit(_) => expr.clone(),
+            Expr::Var(v) => Expr::Var(f(*v)),
+            Expr::Call(op, children) => Expr::Call(f(*op), children.clone()),
+        })
+    }
+
     pub(crate) fn to_sexp(&self) -> Sexp {
         let res = match self {
             Expr::Lit(lit) => Sexp::String(lit.to_string()),
@@ -174,3 +256,59 @@ impl Display for Expr {
         write!(f, "{}", self.to_sexp())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::desugar::Desugar;
+
+    #[test]
+    fn test_literals_round_trip() {
+        let literals = [
+            Literal::F64(OrderedFloat(1e300)),
+            Literal::F64(OrderedFloat(-1e-300)),
+            Literal::F64(OrderedFloat(0.1)),
+            Literal::F64(OrderedFloat(f64::INFINITY)),
+            Literal::F64(OrderedFloat(f64::NEG_INFINITY)),
+            Literal::F64(OrderedFloat(f64::NAN)),
+            Literal::Int(i64::MIN),
+            Literal::String("\u{0}\u{7}\t\r\n\u{1b}\u{7f}".into()),
+            Literal::String("\"quoted\" \\ \u{1F600}".into()),
+            Literal::Unit,
+        ];
+        for literal in literals {
+            let action = Desugar::default()
+                .parse_action(&format!("(let x {literal})"))
+                .unwrap();
+            assert_eq!(action, Action::Let("x".into(), Expr::Lit(literal)));
+        }
+    }
+
+    #[test]
+    fn test_unit_literal_next_to_empty_lists() {
+        let commands = Desugar::default()
+            .parse_program("(function f () Unit) (function g (Unit) i64) (set (g ()) 1)")
+            .unwrap();
+        let schemas: Vec<_> = (commands.iter())
+            .filter_map(|command| match command {
+                Command::Function(decl) => Some(decl.schema.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            schemas,
+            [
+                Schema::new(vec![], "Unit".into()),
+                Schema::new(vec!["Unit".into()], "i64".into()),
+            ]
+        );
+        let Command::Action(action) = &commands[2] else {
+            panic!("not an action: {}", commands[2])
+        };
+        let unit = Expr::Lit(Literal::Unit);
+        assert_eq!(
+            action,
+            &Action::Set("g".into(), vec![unit], Expr::Lit(Literal::Int(1)))
+        );
+    }
+}
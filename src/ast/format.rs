@@ -0,0 +1,399 @@
+use crate::*;
+
+/// The width that formatted lines are kept within where possible.
+const WIDTH: usize = 80;
+/// How far the arguments of a command are indented when it is split over
+/// several lines.
+const INDENT: usize = 2;
+
+/// Format an egglog program, read from the file `filename` if there is one,
+/// canonically, keeping its comments.
+///
+/// A list that fits on the rest of its line is written on one line. A
+/// command that does not fit keeps its positional arguments on its first
+/// line if they all fit there, or else just its first argument, then puts
+/// each remaining argument on its own line, indented by two spaces. Other
+/// lists put each argument after the first on its own line, aligned with the
+/// first, or indented by two spaces if they would not fit that way.
+///
+/// A keyword argument stays on the same line as its value. Keyword arguments
+/// are not reordered: the parser only accepts them in their canonical order
+/// (`:when` before `:ruleset`, `:cost` before `:merge`, and so on).
+///
+/// A module or macro definition is always split over several lines, with
+/// each command of its body laid out like a top-level command.
+///
+/// Runs of blank lines between commands become a single blank line, and
+/// blank lines inside a command are removed. Formatting a formatted program
+/// leaves it unchanged.
+pub fn format_program(input: &str) -> Result<String, Error> {
+    format_program_from(None, input)
+}
+
+/// Like [`format_program`], for `input` read from the file `filename`, which
+/// parse errors name.
+pub fn format_program_with_filename(filename: String, input: &str) -> Result<String, Error> {
+    format_program_from(Some(filename), input)
+}
+
+fn format_program_from(filename: Option<String>, input: &str) -> Result<String, Error> {
+    // only format programs that parse
+    super::desugar::Desugar::default().parse_spanned_program(filename, input)?;
+
+    let mut printer = Printer::default();
+    for node in read_nodes(input) {
+        match &node {
+            Node::Blank => printer.out.push('\n'),
+            Node::Comment { trailing: true, .. } => printer.write(" "),
+            _ if printer.out.is_empty() => {}
+            _ => printer.newline(0),
+        }
+        printer.print(&node, true, 0);
+    }
+    if !printer.out.is_empty() {
+        printer.out.push('\n');
+    }
+    Ok(printer.out)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Node {
+    /// An identifier, literal or keyword, as written.
+    Atom(String),
+    List(Vec<Node>),
+    /// A `;` or `#| |#` comment. A trailing comment follows other code on
+    /// its line.
+    Comment {
+        text: String,
+        trailing: bool,
+    },
+    /// One or more blank lines between top-level nodes.
+    Blank,
+}
+
+/// Split a program that parses into nodes.
+fn read_nodes(input: &str) -> Vec<Node> {
+    // the lists being read, innermost last
+    let mut stack: Vec<Vec<Node>> = vec![vec![]];
+    let mut chars = input.char_indices().peekable();
+    let mut newlines = 0;
+    while let Some((start, c)) = chars.next() {
+        if c == '\n' {
+            newlines += 1;
+            continue;
+        } else if c.is_whitespace() {
+            continue;
+        }
+        if newlines > 1 && stack.len() == 1 && !stack[0].is_empty() {
+            stack[0].push(Node::Blank);
+        }
+        let trailing = newlines == 0;
+        newlines = 0;
+
+        let mut end = start + c.len_utf8();
+        let node = match c {
+            '(' => {
+                stack.push(vec![]);
+                continue;
+            }
+            ')' => Node::List(stack.pop().unwrap()),
+            ';' => {
+                while let Some((i, c)) = chars.next_if(|&(_, c)| c != '\n') {
+                    end = i + c.len_utf8();
+                }
+                Node::Comment {
+                    text: input[start..end].trim_end().to_string(),
+                    trailing: trailing && !(stack.len() == 1 && stack[0].is_empty()),
+                }
+            }
+            '#' if chars.next_if(|&(_, c)| c == '|').is_some() => {
+                let mut bars = false;
+                for (i, c) in chars.by_ref() {
+                    end = i + c.len_utf8();
+                    if bars && c == '#' {
+                        break;
+                    }
+                    bars = c == '|';
+                }
+                Node::Comment {
+                    text: input[start..end].to_string(),
+                    trailing: trailing && !(stack.len() == 1 && stack[0].is_empty()),
+                }
+            }
+            '"' => {
+                // the string ends at the first quote that is neither escaped
+                // by a backslash nor doubled, as in `"say ""hi"""`
+                while let Some((i, c)) = chars.next() {
+                    end = i + c.len_utf8();
+                    if c == '\\' {
+                        if let Some((i, c)) = chars.next() {
+                            end = i + c.len_utf8();
+                        }
+                    } else if c == '"' && chars.next_if(|&(_, c)| c == '"').is_none() {
+                        break;
+                    }
+                }
+                Node::Atom(input[start..end].to_string())
+            }
+            _ => {
+                let is_atom = |&(_, c): &(usize, char)| !(c.is_whitespace() || "();\"".contains(c));
+                while let Some((i, c)) = chars.next_if(is_atom) {
+                    end = i + c.len_utf8();
+                }
+                Node::Atom(input[start..end].to_string())
+            }
+        };
+        stack.last_mut().unwrap().push(node);
+    }
+    stack.pop().unwrap()
+}
+
+/// `node` on a single line, if it has no comments or line breaks.
+fn flat(node: &Node) -> Option<String> {
+    match node {
+        Node::Atom(s) if !s.contains('\n') => Some(s.clone()),
+        Node::List(nodes) => {
+            let nodes = nodes.iter().map(flat).collect::<Option<Vec<_>>>()?;
+            Some(format!("({})", nodes.join(" ")))
+        }
+        _ => None,
+    }
+}
+
+/// Whether `nodes` are those of a command with a body of commands.
+fn has_body(nodes: &[Node]) -> bool {
+    matches!(nodes.first(), Some(Node::Atom(head)) if head == "module" || head == "define-macro")
+}
+
+/// Split the arguments of a list into the groups laid out together: a
+/// keyword with its value, or a single node.
+fn groups(nodes: &[Node]) -> Vec<&[Node]> {
+    let is_keyword = |node: &Node| matches!(node, Node::Atom(s) if s.starts_with(':'));
+    let mut groups = vec![];
+    let mut i = 0;
+    while i < nodes.len() {
+        let len = match nodes.get(i + 1) {
+            Some(next) if is_keyword(&nodes[i]) => {
+                if is_keyword(next) || matches!(next, Node::Comment { .. }) {
+                    1
+                } else {
+                    2
+                }
+            }
+            _ => 1,
+        };
+        groups.push(&nodes[i..i + len]);
+        i += len;
+    }
+    groups
+}
+
+#[derive(Default)]
+struct Printer {
+    out: String,
+    /// The column the next character is written at.
+    col: usize,
+}
+
+impl Printer {
+    fn write(&mut self, s: &str) {
+        self.out.push_str(s);
+        match s.rfind('\n') {
+            Some(i) => self.col = s[i + 1..].chars().count(),
+            None => self.col += s.chars().count(),
+        }
+    }
+
+    fn newline(&mut self, indent: usize) {
+        self.out.push('\n');
+        self.out.extend(std::iter::repeat(' ').take(indent));
+        self.col = indent;
+    }
+
+    /// Write `node`, which is followed by `trail` characters on the same
+    /// line.
+    fn print(&mut self, node: &Node, is_command: bool, trail: usize) {
+        match node {
+            Node::Atom(text) | Node::Comment { text, .. } => self.write(text),
+            Node::List(nodes) => match flat(node) {
+                Some(s)
+                    if self.col + s.chars().count() + trail <= WIDTH
+                        && !(is_command && has_body(nodes)) =>
+                {
+                    self.write(&s)
+                }
+                _ => self.print_lines(nodes, is_command, trail),
+            },
+            Node::Blank => {}
+        }
+    }
+
+    /// Write a list split over several lines.
+    fn print_lines(&mut self, nodes: &[Node], is_command: bool, trail: usize) {
+        let start = self.col;
+        let groups = groups(nodes);
+        let is_comment = |group: &[Node]| matches!(group[0], Node::Comment { .. });
+
+        // the number of groups on the first line, and the column of the rest
+        let (n_first, indent) = match nodes.first() {
+            Some(Node::Atom(_)) if is_command && has_body(nodes) => (2, start + INDENT),
+            Some(Node::Atom(head)) if is_command => {
+                let mut width = start + 1 + head.chars().count();
+                let mut n_first = 1;
+                for group in &groups[1..] {
+                    match &group[0] {
+                        Node::Atom(s) if s.starts_with(':') => break,
+                        node => match flat(node) {
+                            Some(s) => width += 1 + s.chars().count(),
+                            None => {
+                                width = usize::MAX;
+                                break;
+                            }
+                        },
+                    }
+                    n_first += 1;
+                }
+                if n_first == groups.len() {
+                    width = width.saturating_add(trail + 1);
+                }
+                if width > WIDTH {
+                    n_first = n_first.min(2);
+                }
+                (n_first, start + INDENT)
+            }
+            Some(Node::Atom(head)) => {
+                // align the arguments with the first, unless one would not
+                // fit on its line there
+                let align = start + 1 + head.chars().count() + 1;
+                let fits = (groups[1..].iter()).all(|group| {
+                    if is_comment(group) {
+                        return true;
+                    }
+                    let width = group
+                        .iter()
+                        .map(|node| flat(node).map(|s| s.chars().count()));
+                    let width = width.sum::<Option<usize>>().map(|w| w + group.len() - 1);
+                    width.map_or(false, |w| align + w <= WIDTH)
+                });
+                if fits {
+                    (2, align)
+                } else {
+                    (1, start + INDENT)
+                }
+            }
+            _ => (1, start + 1),
+        };
+
+        self.write("(");
+        for (i, group) in groups.iter().enumerate() {
+            if let [Node::Comment { trailing: true, .. }] = group {
+                self.write(" ");
+            } else if is_comment(group) || (i > 0 && (i >= n_first || is_comment(groups[i - 1]))) {
+                self.newline(indent);
+            } else if i > 0 {
+                self.write(" ");
+            }
+
+            let trail = if i == groups.len() - 1 { trail + 1 } else { 0 };
+            if let [keyword, value] = group {
+                self.print(keyword, false, 0);
+                self.write(" ");
+                self.print(value, false, trail);
+            } else {
+                self.print(&group[0], is_command && has_body(nodes), trail);
+            }
+        }
+        if groups.last().map_or(false, |group| is_comment(group)) {
+            self.newline(indent);
+        }
+        self.write(")");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_program() {
+        let input = "; math\n(datatype Math (Num i64) ; numbers\n  (Var String) (Add Math Math) (Mul Math Math))\n\n\n\
+            (rewrite (Add a b) (Add b a) :when ((= a (Num 1))))\n\
+            (rule ((= e (Add a b)) (= a (Num 0))) ((union e b) (union e (Add b (Num 0)))) :ruleset foo)";
+        let expected = "; math\n\
+            (datatype Math\n  (Num i64) ; numbers\n  (Var String)\n  (Add Math Math)\n  (Mul Math Math))\n\n\
+            (rewrite (Add a b) (Add b a) :when ((= a (Num 1))))\n\
+            (rule ((= e (Add a b)) (= a (Num 0))) ((union e b) (union e (Add b (Num 0))))\n  :ruleset foo)\n";
+        let output = format_program(input).unwrap();
+        assert_eq!(output, expected);
+        assert_eq!(format_program(&output).unwrap(), output);
+    }
+
+    #[test]
+    fn test_format_block_comments_and_escapes() {
+        let input = "#| header\n   spans lines |#\n(check  (Str \"a\\\"b)\"))   #| trailing |#\n";
+        let expected = "#| header\n   spans lines |#\n(check (Str \"a\\\"b)\")) #| trailing |#\n";
+        assert_eq!(format_program(input).unwrap(), expected);
+
+        let input = r#"(check  (Str "say ""hi)"""))"#;
+        let expected = "(check (Str \"say \"\"hi)\"\"\"))\n";
+        assert_eq!(format_program(input).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_keywords_out_of_order_do_not_parse() {
+        let rewrite = "(rewrite (Add a b) (Add b a)";
+        let function = "(function f (i64) i64";
+        for (canonical, reordered) in [
+            (
+                format!("{rewrite} :when ((= a b)) :ruleset r)"),
+                format!("{rewrite} :ruleset r :when ((= a b)))"),
+            ),
+            (
+                format!("{function} :cost 2 :merge (max old new))"),
+                format!("{function} :merge (max old new) :cost 2)"),
+            ),
+            (
+                format!("{function} :merge (max old new) :default 0)"),
+                format!("{function} :default 0 :merge (max old new))"),
+            ),
+            (
+                r#"(rule ((f x)) ((g x)) :ruleset r :name "n")"#.to_string(),
+                r#"(rule ((f x)) ((g x)) :name "n" :ruleset r)"#.to_string(),
+            ),
+        ] {
+            assert!(format_program(&canonical).is_ok(), "{canonical}");
+            assert!(format_program(&reordered).is_err(), "{reordered}");
+        }
+    }
+
+    #[test]
+    fn test_format_test_programs() {
+        let desugar = super::super::desugar::Desugar::default();
+        // commands hold the spans of module bodies, so compare them as text
+        let commands = |program: &str| -> Vec<String> {
+            let commands = desugar.parse_program(program).unwrap();
+            commands.iter().map(|command| command.to_string()).collect()
+        };
+        let dir = std::fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/tests")).unwrap();
+        for entry in dir {
+            let path = entry.unwrap().path();
+            if path.extension().map_or(true, |ext| ext != "egg") {
+                continue;
+            }
+            let program = std::fs::read_to_string(&path).unwrap();
+            let formatted = format_program(&program).unwrap();
+            assert_eq!(
+                format_program(&formatted).unwrap(),
+                formatted,
+                "formatting {} twice changed it",
+                path.display()
+            );
+            assert_eq!(
+                commands(&formatted),
+                commands(&program),
+                "formatting {} changed its commands",
+                path.display()
+            );
+        }
+    }
+}
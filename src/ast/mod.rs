@@ -18,6 +18,8 @@ use crate::*;
 mod expr;
 pub use expr::*;
 pub mod desugar;
+mod format;
+pub use format::{format_program, format_program_with_filename};
 
 #[derive(Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub struct Id(usize);
@@ -42,10 +44,80 @@ impl Display for Id {
 
 pub type CommandId = usize;
 
-// TODO put line numbers in metadata
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct Metadata {
     pub id: CommandId,
+    /// The source of the command this was desugared from, if it was parsed.
+    pub span: Option<Span>,
+}
+
+/// A program that was parsed, kept so that errors can point into it.
+#[derive(Debug)]
+pub struct SrcFile {
+    /// The name of the file, if the program was read from one.
+    pub name: Option<String>,
+    pub contents: String,
+}
+
+/// A range of bytes in a [`SrcFile`]. Spans are compared by which parse
+/// their file came from rather than by its contents.
+#[derive(Debug, Clone)]
+pub struct Span {
+    pub file: Arc<SrcFile>,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl PartialEq for Span {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.file, &other.file) && self.start == other.start && self.end == other.end
+    }
+}
+
+impl Eq for Span {}
+
+impl std::hash::Hash for Span {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        Arc::as_ptr(&self.file).hash(state);
+        self.start.hash(state);
+        self.end.hash(state);
+    }
+}
+
+impl Span {
+    /// The source text the span covers.
+    pub fn text(&self) -> &str {
+        &self.file.contents[self.start..self.end]
+    }
+
+    /// The line and column the span starts at, both counting from 1.
+    pub fn line_col(&self) -> (usize, usize) {
+        let before = &self.file.contents[..self.start];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let line = before.matches('\n').count() + 1;
+        (line, before[line_start..].chars().count() + 1)
+    }
+
+    /// The lines the span covers, each on a new line after its number.
+    pub fn quote(&self) -> String {
+        let contents = &self.file.contents;
+        let start = contents[..self.start].rfind('\n').map_or(0, |i| i + 1);
+        let end = (contents[self.end..].find('\n')).map_or(contents.len(), |i| self.end + i);
+        let (first, _) = self.line_col();
+        let mut res = String::new();
+        for (i, line) in contents[start..end].lines().enumerate() {
+            write!(res, "\n{:>4} | {line}", first + i).unwrap();
+        }
+        res
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (line, col) = self.line_col();
+        let name = self.file.name.as_deref().unwrap_or("<input>");
+        write!(f, "{name}:{line}:{col}")
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
@@ -88,6 +160,7 @@ pub enum NCommand {
     },
     Extract {
         variants: usize,
+        mode: ExtractMode,
         var: Symbol,
     },
     Check(Vec<NormFact>),
@@ -97,6 +170,18 @@ pub enum NCommand {
         file: String,
         exprs: Vec<Expr>,
     },
+    OutputTable {
+        name: Symbol,
+        file: String,
+    },
+    Save(String),
+    Load(String),
+    ExportJson(String),
+    ExportDot {
+        file: String,
+        functions: Vec<Symbol>,
+        root: Option<Symbol>,
+    },
     Push(usize),
     Pop(usize),
     Fail(Box<NCommand>),
@@ -104,6 +189,8 @@ pub enum NCommand {
     Input {
         name: Symbol,
         file: String,
+        format: InputFormat,
+        header: bool,
     },
 }
 
@@ -138,8 +225,13 @@ impl NCommand {
                 expr: Expr::Var(*var),
                 config: config.to_run_config(),
             },
-            NCommand::Extract { variants, var } => Command::Extract {
+            NCommand::Extract {
+                variants,
+                mode,
+                var,
+            } => Command::Extract {
                 variants: *variants,
+                mode: *mode,
                 e: Expr::Var(*var),
             },
             NCommand::Check(facts) => {
@@ -151,12 +243,35 @@ impl NCommand {
                 file: file.to_string(),
                 exprs: exprs.clone(),
             },
+            NCommand::OutputTable { name, file } => Command::OutputTable {
+                name: *name,
+                file: file.clone(),
+            },
+            NCommand::Save(file) => Command::Save(file.clone()),
+            NCommand::Load(file) => Command::Load(file.clone()),
+            NCommand::ExportJson(file) => Command::ExportJson(file.clone()),
+            NCommand::ExportDot {
+                file,
+                functions,
+                root,
+            } => Command::ExportDot {
+                file: file.clone(),
+                functions: functions.clone(),
+                root: root.map(Expr::Var),
+            },
             NCommand::Push(n) => Command::Push(*n),
             NCommand::Pop(n) => Command::Pop(*n),
             NCommand::Fail(cmd) => Command::Fail(Box::new(cmd.to_command())),
-            NCommand::Input { name, file } => Command::Input {
+            NCommand::Input {
+                name,
+                file,
+                format,
+                header,
+            } => Command::Input {
                 name: *name,
                 file: file.clone(),
+                format: *format,
+                header: *header,
             },
         }
     }
@@ -183,8 +298,13 @@ impl NCommand {
             },
             NCommand::NormAction(action) => NCommand::NormAction(action.map_exprs(f)),
             NCommand::Simplify { .. } => self.clone(),
-            NCommand::Extract { variants, var } => NCommand::Extract {
+            NCommand::Extract {
+                variants,
+                mode,
+                var,
+            } => NCommand::Extract {
                 variants: *variants,
+                mode: *mode,
                 var: *var,
             },
             NCommand::Check(facts) => {
@@ -196,12 +316,35 @@ impl NCommand {
                 file: file.to_string(),
                 exprs: exprs.clone(),
             },
+            NCommand::OutputTable { name, file } => NCommand::OutputTable {
+                name: *name,
+                file: file.clone(),
+            },
+            NCommand::Save(file) => NCommand::Save(file.clone()),
+            NCommand::Load(file) => NCommand::Load(file.clone()),
+            NCommand::ExportJson(file) => NCommand::ExportJson(file.clone()),
+            NCommand::ExportDot {
+                file,
+                functions,
+                root,
+            } => NCommand::ExportDot {
+                file: file.clone(),
+                functions: functions.clone(),
+                root: *root,
+            },
             NCommand::Push(n) => NCommand::Push(*n),
             NCommand::Pop(n) => NCommand::Pop(*n),
             NCommand::Fail(cmd) => NCommand::Fail(Box::new(cmd.map_exprs(f))),
-            NCommand::Input { name, file } => NCommand::Input {
+            NCommand::Input {
+                name,
+                file,
+                format,
+                header,
+            } => NCommand::Input {
                 name: *name,
                 file: file.clone(),
+                format: *format,
+                header: *header,
             },
         }
     }
@@ -278,6 +421,21 @@ macro_rules! list {
     }};
 }
 
+impl Schedule {
+    pub fn map_symbols(&self, f: &mut impl FnMut(Symbol) -> Symbol) -> Self {
+        match self {
+            Schedule::Saturate(sched) => Schedule::Saturate(Box::new(sched.map_symbols(f))),
+            Schedule::Repeat(size, sched) => {
+                Schedule::Repeat(*size, Box::new(sched.map_symbols(f)))
+            }
+            Schedule::Run(config) => Schedule::Run(config.map_symbols(f)),
+            Schedule::Sequence(scheds) => {
+                Schedule::Sequence(scheds.iter().map(|sched| sched.map_symbols(f)).collect())
+            }
+        }
+    }
+}
+
 impl ToSexp for Schedule {
     fn to_sexp(&self) -> Sexp {
         match self {
@@ -341,6 +499,7 @@ pub enum Command {
     Calc(Vec<IdentSort>, Vec<Expr>),
     Extract {
         variants: usize,
+        mode: ExtractMode,
         e: Expr,
     },
     // TODO: this could just become an empty query
@@ -350,16 +509,53 @@ pub enum Command {
     Input {
         name: Symbol,
         file: String,
+        format: InputFormat,
+        header: bool,
     },
     Output {
         file: String,
         exprs: Vec<Expr>,
     },
+    OutputTable {
+        name: Symbol,
+        file: String,
+    },
+    Save(String),
+    Load(String),
+    ExportJson(String),
+    ExportDot {
+        file: String,
+        functions: Vec<Symbol>,
+        root: Option<Expr>,
+    },
     Push(usize),
     Pop(usize),
     Fail(Box<Command>),
     // TODO desugar include
     Include(String),
+    /// Qualify the names declared by `body` with the module's name, e.g.
+    /// `name.f`, outside the module. Each command of the body has the span
+    /// it was parsed from, if it was parsed.
+    Module {
+        name: Symbol,
+        body: Vec<(Option<Span>, Command)>,
+    },
+    /// Include `file` as the module `name`.
+    Import {
+        file: String,
+        name: Symbol,
+    },
+    /// Make only these names, and those of other `export` commands, visible
+    /// outside the enclosing module. A module without `export` commands
+    /// exports everything.
+    Export(Vec<Symbol>),
+    /// Define a macro, invoked like a function, `(name args...)`, that
+    /// expands to `body` with `params` replaced by the arguments.
+    DefineMacro {
+        name: Symbol,
+        params: Vec<Symbol>,
+        body: Vec<Command>,
+    },
 }
 
 impl ToSexp for Command {
@@ -387,16 +583,84 @@ impl ToSexp for Command {
             Command::Run(config) => config.to_sexp(),
             Command::RunSchedule(sched) => list!("run-schedule", sched),
             Command::Calc(args, exprs) => list!("calc", list!(++ args), ++ exprs),
-            Command::Extract { variants, e } => list!("extract", ":variants", variants, e),
+            Command::Extract { variants, mode, e } => match mode {
+                ExtractMode::Tree => list!("extract", ":variants", variants, e),
+                ExtractMode::Dag => list!("extract", ":dag", ":variants", variants, e),
+                ExtractMode::Exact { time_limit: None } => {
+                    list!("extract", ":exact", ":variants", variants, e)
+                }
+                ExtractMode::Exact {
+                    time_limit: Some(limit),
+                } => list!(
+                    "extract",
+                    ":exact",
+                    ":time-limit",
+                    limit.as_millis().to_string(),
+                    ":variants",
+                    variants,
+                    e
+                ),
+            },
             Command::Check(facts) => list!("check", ++ facts),
             Command::Push(n) => list!("push", n),
             Command::Pop(n) => list!("pop", n),
             Command::Print(name, n) => list!("print", name, n),
             Command::PrintSize(name) => list!("print-size", name),
-            Command::Input { name, file } => list!("input", name, format!("\"{}\"", file)),
-            Command::Output { file, exprs } => list!("output", format!("\"{}\"", file), ++ exprs),
+            Command::Input {
+                name,
+                file,
+                format,
+                header,
+            } => {
+                let mut res = vec![
+                    Sexp::String("input".into()),
+                    Sexp::String(name.to_string()),
+                    Sexp::String(quote(file)),
+                ];
+                if *format == InputFormat::Csv {
+                    res.push(Sexp::String(":csv".into()));
+                }
+                if *header {
+                    res.push(Sexp::String(":header".into()));
+                }
+                Sexp::List(res)
+            }
+            Command::Output { file, exprs } => list!("output", quote(file), ++ exprs),
+            Command::OutputTable { name, file } => {
+                list!("output-table", name, quote(file))
+            }
+            Command::Save(file) => list!("save", quote(file)),
+            Command::Load(file) => list!("load", quote(file)),
+            Command::ExportJson(file) => list!("export-json", quote(file)),
+            Command::ExportDot {
+                file,
+                functions,
+                root,
+            } => {
+                let mut res = vec![Sexp::String("export-dot".into()), Sexp::String(quote(file))];
+                if !functions.is_empty() {
+                    res.push(Sexp::String(":functions".into()));
+                    res.push(list!(++ functions));
+                }
+                if let Some(root) = root {
+                    res.push(Sexp::String(":root".into()));
+                    res.push(root.to_sexp());
+                }
+                Sexp::List(res)
+            }
             Command::Fail(cmd) => list!("fail", cmd),
-            Command::Include(file) => list!("include", format!("\"{}\"", file)),
+            Command::Include(file) => list!("include", quote(file)),
+            Command::Module { name, body } => {
+                let body: Vec<&Command> = body.iter().map(|(_, command)| command).collect();
+                list!("module", name, ++ body)
+            }
+            Command::Import { file, name } => {
+                list!("import", quote(file), ":as", name)
+            }
+            Command::Export(names) => list!("export", ++ names),
+            Command::DefineMacro { name, params, body } => {
+                list!("define-macro", list!(name, ++ params), ++ body)
+            }
             Command::Simplify { expr, config } => match &config.until {
                 Some(until) => list!("simplify", config.limit, expr, ":until", ++ until),
                 None => list!("simplify", config.limit, expr),
@@ -405,6 +669,141 @@ impl ToSexp for Command {
     }
 }
 
+impl Command {
+    /// Rename the sorts, functions, globals and rulesets that the command
+    /// declares or refers to, along with the variables of its rules. The
+    /// bodies of modules and macros are left alone.
+    pub fn map_symbols(&self, f: &mut impl FnMut(Symbol) -> Symbol) -> Command {
+        match self {
+            Command::Datatype { name, variants } => Command::Datatype {
+                name: f(*name),
+                variants: (variants.iter())
+                    .map(|variant| Variant {
+                        name: f(variant.name),
+                        types: variant.types.iter().map(|t| f(*t)).collect(),
+                        cost: variant.cost,
+                        unextractable: variant.unextractable,
+                    })
+                    .collect(),
+            },
+            Command::Declare { name, sort } => Command::Declare {
+                name: f(*name),
+                sort: f(*sort),
+            },
+            Command::Sort(name, presort) => Command::Sort(
+                f(*name),
+                (presort.as_ref()).map(|(presort, args)| {
+                    (*presort, args.iter().map(|e| e.map_symbols(f)).collect())
+                }),
+            ),
+            Command::Function(decl) => Command::Function(decl.map_symbols(f)),
+            Command::Define { name, expr, cost } => Command::Define {
+                name: f(*name),
+                expr: expr.map_symbols(f),
+                cost: *cost,
+            },
+            Command::AddRuleset(name) => Command::AddRuleset(f(*name)),
+            Command::Rule {
+                name,
+                ruleset,
+                rule,
+            } => Command::Rule {
+                name: *name,
+                ruleset: f(*ruleset),
+                rule: rule.map_symbols(f),
+            },
+            Command::Rewrite(ruleset, rewrite) => {
+                Command::Rewrite(f(*ruleset), rewrite.map_symbols(f))
+            }
+            Command::BiRewrite(ruleset, rewrite) => {
+                Command::BiRewrite(f(*ruleset), rewrite.map_symbols(f))
+            }
+            Command::Action(action) => Command::Action(action.map_symbols(f)),
+            Command::Run(config) => Command::Run(config.map_symbols(f)),
+            Command::RunSchedule(sched) => Command::RunSchedule(sched.map_symbols(f)),
+            Command::Simplify { expr, config } => Command::Simplify {
+                expr: expr.map_symbols(f),
+                config: config.map_symbols(f),
+            },
+            Command::Calc(idents, exprs) => Command::Calc(
+                (idents.iter())
+                    .map(|IdentSort { ident, sort }| IdentSort {
+                        ident: f(*ident),
+                        sort: f(*sort),
+                    })
+                    .collect(),
+                exprs.iter().map(|e| e.map_symbols(f)).collect(),
+            ),
+            Command::Extract { variants, mode, e } => Command::Extract {
+                variants: *variants,
+                mode: *mode,
+                e: e.map_symbols(f),
+            },
+            Command::Check(facts) => {
+                Command::Check(facts.iter().map(|fact| fact.map_symbols(f)).collect())
+            }
+            Command::Print(name, n) => Command::Print(f(*name), *n),
+            Command::PrintSize(name) => Command::PrintSize(f(*name)),
+            Command::Input {
+                name,
+                file,
+                format,
+                header,
+            } => Command::Input {
+                name: f(*name),
+                file: file.clone(),
+                format: *format,
+                header: *header,
+            },
+            Command::Output { file, exprs } => Command::Output {
+                file: file.clone(),
+                exprs: exprs.iter().map(|e| e.map_symbols(f)).collect(),
+            },
+            Command::OutputTable { name, file } => Command::OutputTable {
+                name: f(*name),
+                file: file.clone(),
+            },
+            Command::ExportDot {
+                file,
+                functions,
+                root,
+            } => Command::ExportDot {
+                file: file.clone(),
+                functions: functions.iter().map(|name| f(*name)).collect(),
+                root: root.as_ref().map(|e| e.map_symbols(f)),
+            },
+            Command::Fail(cmd) => Command::Fail(Box::new(cmd.map_symbols(f))),
+            Command::SetOption { .. }
+            | Command::Save(_)
+            | Command::Load(_)
+            | Command::ExportJson(_)
+            | Command::Push(_)
+            | Command::Pop(_)
+            | Command::Include(_)
+            | Command::Module { .. }
+            | Command::Import { .. }
+            | Command::Export(_)
+            | Command::DefineMacro { .. } => self.clone(),
+        }
+    }
+
+    /// The sorts, functions, globals and rulesets that the command declares.
+    pub(crate) fn declared_names(&self) -> Vec<Symbol> {
+        match self {
+            Command::Datatype { name, variants } => std::iter::once(*name)
+                .chain(variants.iter().map(|variant| variant.name))
+                .collect(),
+            Command::Sort(name, _)
+            | Command::Declare { name, .. }
+            | Command::Define { name, .. }
+            | Command::AddRuleset(name)
+            | Command::Action(Action::Let(name, _)) => vec![*name],
+            Command::Function(decl) => vec![decl.name],
+            _ => vec![],
+        }
+    }
+}
+
 impl Display for NormCommand {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.to_command())
@@ -430,6 +829,33 @@ impl Display for Command {
     }
 }
 
+/// How `extract` measures the cost of a term.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ExtractMode {
+    /// Every occurrence of a subterm is counted separately.
+    #[default]
+    Tree,
+    /// Shared subterms are counted once, and the result is let-bound.
+    Dag,
+    /// Like `Dag`, but searches for a term with minimum cost instead of
+    /// building one greedily, giving up after `time_limit` (ten seconds if
+    /// none is given).
+    Exact { time_limit: Option<Duration> },
+}
+
+/// How `input` splits a file into fields. In both formats a field may be
+/// quoted as in CSV, which is how `output-table` writes strings containing
+/// tabs or newlines.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum InputFormat {
+    /// Fields are separated by tabs. A field starting with `"` is quoted, so
+    /// a string field can't start with a bare quote.
+    #[default]
+    Tsv,
+    /// Fields are separated by commas.
+    Csv,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct IdentSort {
     pub ident: Symbol,
@@ -471,6 +897,17 @@ impl ToSexp for RunConfig {
     }
 }
 
+impl RunConfig {
+    pub fn map_symbols(&self, f: &mut impl FnMut(Symbol) -> Symbol) -> Self {
+        RunConfig {
+            ruleset: f(self.ruleset),
+            limit: self.limit,
+            until: (self.until.as_ref())
+                .map(|facts| facts.iter().map(|fact| fact.map_symbols(f)).collect()),
+        }
+    }
+}
+
 // TODO get rid of limit, just use Repeat
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct NormRunConfig {
@@ -500,6 +937,9 @@ pub struct FunctionDecl {
     pub merge: Option<Expr>,
     pub merge_action: Vec<Action>,
     pub cost: Option<usize>,
+    /// Whether extraction must avoid this function's e-nodes, e.g. because
+    /// it is a helper rather than a constructor.
+    pub unextractable: bool,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
@@ -507,6 +947,7 @@ pub struct Variant {
     pub name: Symbol,
     pub types: Vec<Symbol>,
     pub cost: Option<usize>,
+    pub unextractable: bool,
 }
 
 impl ToSexp for Variant {
@@ -519,6 +960,9 @@ impl ToSexp for Variant {
             res.push(Sexp::String(":cost".into()));
             res.push(Sexp::String(cost.to_string()));
         }
+        if self.unextractable {
+            res.push(Sexp::String(":unextractable".into()));
+        }
         Sexp::List(res)
     }
 }
@@ -542,6 +986,23 @@ impl Schema {
 }
 
 impl FunctionDecl {
+    /// Rename the function, its sorts, and the functions its default and
+    /// merge refer to.
+    pub fn map_symbols(&self, f: &mut impl FnMut(Symbol) -> Symbol) -> Self {
+        FunctionDecl {
+            name: f(self.name),
+            schema: Schema {
+                input: self.schema.input.iter().map(|s| f(*s)).collect(),
+                output: f(self.schema.output),
+            },
+            default: self.default.as_ref().map(|e| e.map_symbols(f)),
+            merge: self.merge.as_ref().map(|e| e.map_symbols(f)),
+            merge_action: self.merge_action.iter().map(|a| a.map_symbols(f)).collect(),
+            cost: self.cost,
+            unextractable: self.unextractable,
+        }
+    }
+
     pub fn relation(name: Symbol, input: Vec<Symbol>) -> Self {
         Self {
             name,
@@ -553,6 +1014,7 @@ impl FunctionDecl {
             merge_action: vec![],
             default: None,
             cost: None,
+            unextractable: false,
         }
     }
 }
@@ -577,6 +1039,10 @@ impl ToSexp for FunctionDecl {
             ]);
         }
 
+        if self.unextractable {
+            res.push(Sexp::String(":unextractable".into()));
+        }
+
         if !self.merge_action.is_empty() {
             res.push(Sexp::String(":on_merge".into()));
             res.push(Sexp::List(
@@ -662,6 +1128,10 @@ impl Fact {
             Fact::Fact(expr) => Fact::Fact(f(expr)),
         }
     }
+
+    pub fn map_symbols(&self, f: &mut impl FnMut(Symbol) -> Symbol) -> Fact {
+        self.map_exprs(&mut |e| e.map_symbols(f))
+    }
 }
 
 impl Display for NormFact {
@@ -760,7 +1230,7 @@ impl ToSexp for Action {
             }
             Action::Union(lhs, rhs) => list!("union", lhs, rhs),
             Action::Delete(lhs, args) => list!("delete", list!(lhs, ++ args)),
-            Action::Panic(msg) => list!("panic", format!("\"{}\"", msg.clone())),
+            Action::Panic(msg) => list!("panic", quote(msg)),
             Action::Expr(e) => e.to_sexp(),
         }
     }
@@ -785,6 +1255,19 @@ impl Action {
         }
     }
 
+    /// Rename the variables and functions in the action, including the
+    /// ones it binds or sets.
+    pub fn map_symbols(&self, f: &mut impl FnMut(Symbol) -> Symbol) -> Self {
+        let action = self.map_exprs(&mut |e| e.map_symbols(f));
+        match action {
+            Action::Let(lhs, rhs) => Action::Let(f(lhs), rhs),
+            Action::Set(lhs, args, rhs) => Action::Set(f(lhs), args, rhs),
+            Action::SetNoTrack(lhs, args, rhs) => Action::SetNoTrack(f(lhs), args, rhs),
+            Action::Delete(lhs, args) => Action::Delete(f(lhs), args),
+            action => action,
+        }
+    }
+
     pub fn replace_canon(&self, canon: &HashMap<Symbol, Expr>) -> Self {
         match self {
             Action::Let(lhs, rhs) => Action::Let(*lhs, rhs.replace_canon(canon)),
@@ -878,7 +1361,7 @@ impl Rule {
         }
         if name != "".into() {
             res.push(Sexp::String(":name".into()));
-            res.push(Sexp::String(format!("\"{}\"", name)));
+            res.push(Sexp::String(quote(name.as_str())));
         }
         Sexp::List(res)
     }
@@ -890,6 +1373,13 @@ impl Rule {
         }
     }
 
+    pub fn map_symbols(&self, f: &mut impl FnMut(Symbol) -> Symbol) -> Self {
+        Rule {
+            head: self.head.iter().map(|a| a.map_symbols(f)).collect(),
+            body: self.body.iter().map(|fact| fact.map_symbols(f)).collect(),
+        }
+    }
+
     pub(crate) fn fmt_with_ruleset(
         &self,
         f: &mut std::fmt::Formatter<'_>,
@@ -926,7 +1416,7 @@ impl Rule {
             "".into()
         };
         let name = if name != "".into() {
-            format!(":name \"{}\"", name)
+            format!(":name {}", quote(name.as_str()))
         } else {
             "".into()
         };
@@ -974,6 +1464,16 @@ impl Rewrite {
     }
 }
 
+impl Rewrite {
+    pub fn map_symbols(&self, f: &mut impl FnMut(Symbol) -> Symbol) -> Self {
+        Rewrite {
+            lhs: self.lhs.map_symbols(f),
+            rhs: self.rhs.map_symbols(f),
+            conditions: self.conditions.iter().map(|c| c.map_symbols(f)).collect(),
+        }
+    }
+}
+
 impl Display for Rewrite {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.to_sexp("".into(), false))
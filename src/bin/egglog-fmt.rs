@@ -0,0 +1,74 @@
+//! Formats `.egg` files in place, or standard input to standard output when
+//! no files are given.
+
+use clap::Parser;
+use egg_smol::ast::{format_program, format_program_with_filename};
+use std::io::Read;
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+struct Args {
+    /// Exit with an error if any input is not formatted, instead of
+    /// formatting it
+    #[clap(long)]
+    check: bool,
+    inputs: Vec<PathBuf>,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    if args.inputs.is_empty() {
+        let mut input = String::new();
+        std::io::stdin()
+            .read_to_string(&mut input)
+            .expect("Failed to read standard input");
+        match format_program(&input) {
+            Ok(output) if args.check && output != input => {
+                eprintln!("<stdin> is not formatted");
+                std::process::exit(1)
+            }
+            Ok(output) => {
+                if !args.check {
+                    print!("{output}")
+                }
+            }
+            Err(err) => {
+                eprintln!("{err}");
+                std::process::exit(1)
+            }
+        }
+        return;
+    }
+
+    let mut failed = false;
+    for input in &args.inputs {
+        let s = std::fs::read_to_string(input).unwrap_or_else(|_| {
+            let arg = input.to_string_lossy();
+            panic!("Failed to read file {arg}")
+        });
+        let output = match format_program_with_filename(input.to_string_lossy().into_owned(), &s) {
+            Ok(output) => output,
+            Err(err) => {
+                eprintln!("{err}");
+                failed = true;
+                continue;
+            }
+        };
+        if output == s {
+            continue;
+        }
+        if args.check {
+            eprintln!("{} is not formatted", input.display());
+            failed = true;
+        } else {
+            std::fs::write(input, output).unwrap_or_else(|_| {
+                let arg = input.to_string_lossy();
+                panic!("Failed to write file {arg}")
+            });
+        }
+    }
+    if failed {
+        std::process::exit(1)
+    }
+}
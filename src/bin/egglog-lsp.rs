@@ -0,0 +1,486 @@
+//! A language server for `.egg` files, speaking LSP over stdin and stdout.
+//!
+//! Every top-level command is parsed and typechecked on its own, in order,
+//! so that an error is reported on the command that caused it and the
+//! commands after it are still checked.
+
+use egg_smol::ast::{Action, Command, Symbol};
+use egg_smol::{EGraph, Error};
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+    Notification as NotificationTrait, PublishDiagnostics,
+};
+use lsp_types::request::{Completion, GotoDefinition, HoverRequest, Request as RequestTrait};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionResponse, Diagnostic,
+    DiagnosticSeverity, GotoDefinitionResponse, Hover, HoverContents, HoverProviderCapability,
+    Location, MarkupContent, MarkupKind, OneOf, Position, PublishDiagnosticsParams, Range,
+    ServerCapabilities, TextDocumentPositionParams, TextDocumentSyncCapability,
+    TextDocumentSyncKind, Url,
+};
+use std::collections::BTreeMap;
+
+fn main() {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        completion_provider: Some(CompletionOptions::default()),
+        ..Default::default()
+    };
+    connection
+        .initialize(serde_json::to_value(capabilities).unwrap())
+        .expect("Failed to initialize the language server");
+
+    let mut documents: BTreeMap<Url, Analysis> = BTreeMap::new();
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request).unwrap() {
+                    break;
+                }
+                let response = handle_request(&documents, request);
+                connection.sender.send(Message::Response(response)).unwrap();
+            }
+            Message::Notification(notification) => {
+                if let Some(params) = handle_notification(&mut documents, notification) {
+                    let notification = Notification::new(PublishDiagnostics::METHOD.into(), params);
+                    connection
+                        .sender
+                        .send(Message::Notification(notification))
+                        .unwrap();
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+    io_threads.join().unwrap();
+}
+
+/// Update `documents` for a notification from the client, returning the new
+/// diagnostics of the document it changed, if any.
+fn handle_notification(
+    documents: &mut BTreeMap<Url, Analysis>,
+    notification: Notification,
+) -> Option<PublishDiagnosticsParams> {
+    let (uri, text) = match notification.method.as_str() {
+        DidOpenTextDocument::METHOD => {
+            let params: lsp_types::DidOpenTextDocumentParams =
+                serde_json::from_value(notification.params).ok()?;
+            (params.text_document.uri, params.text_document.text)
+        }
+        DidChangeTextDocument::METHOD => {
+            let params: lsp_types::DidChangeTextDocumentParams =
+                serde_json::from_value(notification.params).ok()?;
+            let text = params.content_changes.into_iter().last()?.text;
+            (params.text_document.uri, text)
+        }
+        DidCloseTextDocument::METHOD => {
+            let params: lsp_types::DidCloseTextDocumentParams =
+                serde_json::from_value(notification.params).ok()?;
+            documents.remove(&params.text_document.uri);
+            return Some(PublishDiagnosticsParams::new(
+                params.text_document.uri,
+                vec![],
+                None,
+            ));
+        }
+        _ => return None,
+    };
+    let analysis = Analysis::new(text);
+    let params = PublishDiagnosticsParams::new(uri.clone(), analysis.diagnostics.clone(), None);
+    documents.insert(uri, analysis);
+    Some(params)
+}
+
+fn handle_request(documents: &BTreeMap<Url, Analysis>, request: Request) -> Response {
+    let id = request.id.clone();
+    let result = match request.method.as_str() {
+        HoverRequest::METHOD => with_position(documents, request, |analysis, _, offset| {
+            let word = analysis.word_at(offset)?;
+            let value = match analysis.symbols.get(word) {
+                Some((_, description)) => format!("```egglog\n{description}\n```"),
+                None if analysis.primitives.iter().any(|p| p == word) => {
+                    format!("primitive `{word}`")
+                }
+                None => return None,
+            };
+            Some(serde_json::to_value(Hover {
+                contents: HoverContents::Markup(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value,
+                }),
+                range: None,
+            }))
+        }),
+        GotoDefinition::METHOD => with_position(documents, request, |analysis, uri, offset| {
+            let (range, _) = analysis.symbols.get(analysis.word_at(offset)?)?;
+            let location = Location::new(uri.clone(), *range);
+            Some(serde_json::to_value(GotoDefinitionResponse::Scalar(
+                location,
+            )))
+        }),
+        Completion::METHOD => with_position(documents, request, |analysis, _, _| {
+            let primitives = analysis.primitives.iter().map(|name| CompletionItem {
+                label: name.clone(),
+                kind: Some(CompletionItemKind::OPERATOR),
+                ..Default::default()
+            });
+            let symbols = analysis
+                .symbols
+                .iter()
+                .map(|(name, (_, description))| CompletionItem {
+                    label: name.clone(),
+                    kind: Some(CompletionItemKind::FUNCTION),
+                    detail: Some(description.clone()),
+                    ..Default::default()
+                });
+            let items = primitives.chain(symbols).collect();
+            Some(serde_json::to_value(CompletionResponse::Array(items)))
+        }),
+        method => {
+            return Response::new_err(
+                id,
+                ErrorCode::MethodNotFound as i32,
+                format!("Unsupported request {method}"),
+            )
+        }
+    };
+    match result {
+        Ok(result) => Response::new_ok(id, result),
+        Err(message) => Response::new_err(id, ErrorCode::InvalidParams as i32, message),
+    }
+}
+
+/// Answer a request about a position in a document with `f`, which is given
+/// the document's analysis and URI and the position as a byte offset, and
+/// answers `None` if there is nothing to say.
+fn with_position(
+    documents: &BTreeMap<Url, Analysis>,
+    request: Request,
+    f: impl FnOnce(&Analysis, &Url, usize) -> Option<serde_json::Result<serde_json::Value>>,
+) -> Result<serde_json::Value, String> {
+    let params: TextDocumentPositionParams =
+        serde_json::from_value(request.params).map_err(|e| e.to_string())?;
+    let uri = &params.text_document.uri;
+    let analysis = (documents.get(uri)).ok_or_else(|| format!("Unknown document {uri}"))?;
+    let offset = analysis.offset(params.position);
+    match f(analysis, uri, offset) {
+        Some(result) => result.map_err(|e| e.to_string()),
+        None => Ok(serde_json::Value::Null),
+    }
+}
+
+/// What the language server knows about a document.
+struct Analysis {
+    text: String,
+    /// The byte offset of the start of every line.
+    line_starts: Vec<usize>,
+    diagnostics: Vec<Diagnostic>,
+    /// Where every sort, function, ruleset and global is declared, and how
+    /// to describe it.
+    symbols: BTreeMap<String, (Range, String)>,
+    primitives: Vec<String>,
+}
+
+impl Analysis {
+    fn new(text: String) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        let mut analysis = Analysis {
+            text,
+            line_starts,
+            diagnostics: vec![],
+            symbols: BTreeMap::new(),
+            primitives: vec![],
+        };
+
+        let text = analysis.text.clone();
+        let mut egraph = EGraph::default();
+        for (start, end) in top_level_forms(&text) {
+            let form = &text[start..end];
+            let commands = match egraph.parse_program(form) {
+                Ok(commands) => commands,
+                Err(Error::Spanned(span, err)) => {
+                    analysis.error(start + span.start, start + span.end, &err);
+                    continue;
+                }
+                Err(err) => {
+                    analysis.error(start, end, &err);
+                    continue;
+                }
+            };
+            let declared: Vec<_> = commands.iter().flat_map(declarations).collect();
+            if let Err(err) = egraph.process_commands(commands) {
+                analysis.error(start, end, &err);
+            }
+            for (name, description) in declared {
+                let description = description.unwrap_or_else(|| {
+                    let sort = egraph.type_info().global_types.get(&name);
+                    let sort = sort.map_or("?".into(), |sort| sort.name().to_string());
+                    format!("(declare {name} {sort})")
+                });
+                let from = start + find_word(form, name.as_str()).unwrap_or(0);
+                let range = analysis.range(from, from + name.as_str().len());
+                analysis
+                    .symbols
+                    .insert(name.to_string(), (range, description));
+            }
+        }
+
+        let mut primitives: Vec<String> = (egraph.type_info().primitives.keys())
+            .map(|name| name.to_string())
+            .collect();
+        primitives.sort();
+        analysis.primitives = primitives;
+        analysis
+    }
+
+    fn error(&mut self, from: usize, to: usize, err: &Error) {
+        let mut diagnostic = Diagnostic::new_simple(self.range(from, to), err.to_string());
+        diagnostic.severity = Some(DiagnosticSeverity::ERROR);
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn position(&self, offset: usize) -> Position {
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let start = self.line_starts[line];
+        let character = self.text[start..offset].encode_utf16().count();
+        Position::new(line as u32, character as u32)
+    }
+
+    fn range(&self, from: usize, to: usize) -> Range {
+        Range::new(self.position(from), self.position(to))
+    }
+
+    fn offset(&self, position: Position) -> usize {
+        let Some(&start) = self.line_starts.get(position.line as usize) else {
+            return self.text.len();
+        };
+        let mut units = 0;
+        for (i, c) in self.text[start..].char_indices() {
+            if units >= position.character as usize || c == '\n' {
+                return start + i;
+            }
+            units += c.len_utf16();
+        }
+        self.text.len()
+    }
+
+    /// The identifier around `offset`.
+    fn word_at(&self, offset: usize) -> Option<&str> {
+        let start = (self.text[..offset].char_indices().rev())
+            .find(|&(_, c)| is_delimiter(c))
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        let end = self.text[offset..]
+            .find(is_delimiter)
+            .map_or(self.text.len(), |i| offset + i);
+        let word = &self.text[start..end];
+        (!word.is_empty()).then_some(word)
+    }
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | ')' | '"' | ';')
+}
+
+/// The byte offset of the first occurrence of `word` in `text` as a whole
+/// identifier.
+fn find_word(text: &str, word: &str) -> Option<usize> {
+    text.match_indices(word).map(|(i, _)| i).find(|&i| {
+        let before = text[..i].chars().next_back().map_or(true, is_delimiter);
+        let after = text[i + word.len()..]
+            .chars()
+            .next()
+            .map_or(true, is_delimiter);
+        before && after
+    })
+}
+
+/// The byte spans of the top-level forms in `text`: parenthesized commands,
+/// and any other text outside of comments, which the parser will reject.
+fn top_level_forms(text: &str) -> Vec<(usize, usize)> {
+    let mut forms = vec![];
+    let mut depth = 0;
+    let mut start = None;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let comment = c == ';' || (c == '#' && chars.next_if(|&(_, c)| c == '|').is_some());
+        if depth == 0 && (c.is_whitespace() || comment || c == '(') {
+            forms.extend(start.take().map(|start| (start, i)));
+        }
+        match c {
+            ';' => {
+                chars.find(|&(_, c)| c == '\n');
+            }
+            '#' if comment => {
+                while let Some((_, c)) = chars.next() {
+                    if c == '|' && chars.next_if(|&(_, c)| c == '#').is_some() {
+                        break;
+                    }
+                }
+            }
+            '"' => {
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            _ => {}
+        }
+        if comment || (depth == 0 && c.is_whitespace()) {
+            continue;
+        }
+        start.get_or_insert(i);
+        if depth == 0 && c == ')' {
+            forms.extend(start.take().map(|start| (start, i + 1)));
+        }
+    }
+    forms.extend(start.map(|start| (start, text.len())));
+    forms
+}
+
+/// The names declared by `command`, with a description for hovering, which
+/// is `None` for globals since their sort is only known after typechecking.
+fn declarations(command: &Command) -> Vec<(Symbol, Option<String>)> {
+    let list = |names: &[Symbol]| {
+        let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        names.join(" ")
+    };
+    match command {
+        Command::Datatype { name, variants } => {
+            let sort = (*name, Some(format!("(sort {name})")));
+            let variants = variants.iter().map(|variant| {
+                let types = list(&variant.types);
+                let description = format!("(function {} ({types}) {name})", variant.name);
+                (variant.name, Some(description))
+            });
+            std::iter::once(sort).chain(variants).collect()
+        }
+        Command::Sort(name, _) | Command::AddRuleset(name) => {
+            vec![(*name, Some(command.to_string()))]
+        }
+        Command::Function(decl) => {
+            let input = list(&decl.schema.input);
+            let description = format!("(function {} ({input}) {})", decl.name, decl.schema.output);
+            vec![(decl.name, Some(description))]
+        }
+        Command::Declare { name, sort } => vec![(*name, Some(format!("(declare {name} {sort})")))],
+        Command::Define { name, .. } | Command::Action(Action::Let(name, _)) => vec![(*name, None)],
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOCUMENT: &str = r#"(datatype Math (Num i64))
+; (not a form)
+(let two (Num 2)) #| (nor
+this) |#
+(check (= two (Num "two")))
+(let four (Add two two))"#;
+
+    fn text(range: &Range, analysis: &Analysis) -> String {
+        let from = analysis.offset(range.start);
+        let to = analysis.offset(range.end);
+        analysis.text[from..to].to_string()
+    }
+
+    #[test]
+    fn test_top_level_forms() {
+        let forms = |text| -> Vec<&str> {
+            (top_level_forms(text).into_iter())
+                .map(|(start, end)| &text[start..end])
+                .collect()
+        };
+        assert_eq!(
+            forms(DOCUMENT),
+            [
+                "(datatype Math (Num i64))",
+                "(let two (Num 2))",
+                r#"(check (= two (Num "two")))"#,
+                "(let four (Add two two))",
+            ]
+        );
+        assert_eq!(
+            forms(r#"stray (print-size Num) "(" (open"#),
+            ["stray", "(print-size Num)", r#""(""#, "(open"]
+        );
+    }
+
+    #[test]
+    fn test_words() {
+        assert_eq!(find_word("(Numeral Num)", "Num"), Some(9));
+        assert_eq!(find_word("(Numeral)", "Num"), None);
+
+        let analysis = Analysis::new("(let x-1 (Num 2))".into());
+        assert_eq!(analysis.word_at(6), Some("x-1"));
+        assert_eq!(analysis.word_at(10), Some("Num"));
+        assert_eq!(analysis.word_at(0), None);
+
+        // delimiters may be more than one byte long
+        let analysis = Analysis::new("(let\u{a0}x (Num\u{3000}2))".into());
+        assert_eq!(analysis.word_at(6), Some("x"));
+        assert_eq!(analysis.word_at(8), None);
+        assert_eq!(analysis.word_at(12), Some("Num"));
+        assert_eq!(analysis.word_at(15), Some("2"));
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let analysis = Analysis::new(DOCUMENT.into());
+        // each form is checked on its own, so the error in `check` does not
+        // hide the one in the `let` after it
+        let errors: Vec<(u32, String)> = (analysis.diagnostics.iter())
+            .map(|diagnostic| (diagnostic.range.start.line, diagnostic.message.clone()))
+            .collect();
+        assert_eq!(errors.len(), 2, "{errors:?}");
+        assert_eq!(errors[0].0, 4);
+        assert_eq!(errors[1].0, 5);
+        assert!(errors[1].1.contains("Add"), "{}", errors[1].1);
+        assert_eq!(
+            text(&analysis.diagnostics[1].range, &analysis),
+            "(let four (Add two two))"
+        );
+    }
+
+    #[test]
+    fn test_goto_definition() {
+        let mut documents = BTreeMap::new();
+        let uri = Url::parse("file:///test.egg").unwrap();
+        documents.insert(uri.clone(), Analysis::new(DOCUMENT.into()));
+        let goto = |line, character| {
+            let params = TextDocumentPositionParams::new(
+                lsp_types::TextDocumentIdentifier::new(uri.clone()),
+                Position::new(line, character),
+            );
+            let request = Request::new(0.into(), GotoDefinition::METHOD.into(), params);
+            handle_request(&documents, request).result.unwrap()
+        };
+
+        // `two` in the `check` goes to its `let`
+        let location: Location = serde_json::from_value(goto(4, 11)).unwrap();
+        assert_eq!(location.uri, uri);
+        assert_eq!(
+            location.range,
+            Range::new(Position::new(2, 5), Position::new(2, 8))
+        );
+        // `Num` goes to its variant in the datatype
+        let location: Location = serde_json::from_value(goto(2, 11)).unwrap();
+        assert_eq!(location.range.start, Position::new(0, 16));
+        // primitives and unknown names have no definition
+        assert_eq!(goto(4, 8), serde_json::Value::Null);
+    }
+}
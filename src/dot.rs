@@ -0,0 +1,205 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::*;
+
+/// An e-node to draw, with its eq-sort children and its label, which shows
+/// its primitive arguments inline.
+struct DotNode {
+    class: Option<Id>,
+    label: String,
+    children: Vec<Id>,
+}
+
+impl EGraph {
+    /// Render the e-graph in Graphviz DOT format, with one cluster per
+    /// canonical e-class and edges from e-nodes to the classes of their
+    /// children. Global variables introduced by `define` are left out.
+    ///
+    /// If `functions` is not empty, only e-nodes of those functions are drawn.
+    /// If `root` is given, only the e-classes reachable from it are drawn,
+    /// which leaves out e-nodes of functions with primitive outputs.
+    pub fn to_dot(&mut self, functions: &[Symbol], root: Option<&Expr>) -> Result<String, Error> {
+        self.rebuild()?;
+        for &name in functions {
+            self.functions.get(&name).ok_or(TypeError::Unbound(name))?;
+        }
+        let root = match root {
+            Some(expr) => Some(self.eval_expr(expr, None, false)?.1),
+            None => None,
+        };
+
+        let mut names: Vec<Symbol> = (self.functions.iter())
+            .filter(|(name, function)| {
+                !function.is_variable && (functions.is_empty() || functions.contains(name))
+            })
+            .map(|(&name, _)| name)
+            .collect();
+        names.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+
+        let mut nodes = vec![];
+        for name in names {
+            let function = &self.functions[&name];
+            for (inputs, output) in function.nodes.iter() {
+                let mut label = name.to_string();
+                let mut children = vec![];
+                for &input in inputs {
+                    match self.dot_class(input) {
+                        Some(id) => children.push(id),
+                        None => write!(label, " {}", self.dot_leaf(input)).unwrap(),
+                    }
+                }
+                let class = self.dot_class(output.value);
+                if class.is_none() {
+                    write!(label, " = {}", self.dot_leaf(output.value)).unwrap();
+                }
+                nodes.push(DotNode {
+                    class,
+                    label,
+                    children,
+                });
+            }
+        }
+
+        if let Some(root) = root {
+            let reachable = dot_reachable(&nodes, self.dot_class(root));
+            nodes.retain(|node| node.class.map_or(false, |id| reachable.contains(&id)));
+        }
+
+        let mut clusters: BTreeMap<Id, Vec<usize>> = BTreeMap::new();
+        for (i, node) in nodes.iter().enumerate() {
+            for &child in &node.children {
+                clusters.entry(child).or_default();
+            }
+            if let Some(class) = node.class {
+                clusters.entry(class).or_default().push(i);
+            }
+        }
+
+        let mut dot = String::new();
+        writeln!(dot, "digraph egraph {{").unwrap();
+        writeln!(dot, "  compound=true;").unwrap();
+        writeln!(dot, "  clusterrank=local;").unwrap();
+        for (class, members) in &clusters {
+            writeln!(dot, "  subgraph cluster_{class} {{").unwrap();
+            writeln!(dot, "    style=dotted;").unwrap();
+            writeln!(dot, "    label=\"{class}\";").unwrap();
+            writeln!(dot, "    c{class} [shape=point, style=invis];").unwrap();
+            for &i in members {
+                writeln!(dot, "    n{i} [label={}];", dot_quote(&nodes[i].label)).unwrap();
+            }
+            writeln!(dot, "  }}").unwrap();
+        }
+        for (i, node) in nodes.iter().enumerate() {
+            if node.class.is_none() {
+                let label = dot_quote(&node.label);
+                writeln!(dot, "  n{i} [label={label}, shape=box];").unwrap();
+            }
+        }
+        for (i, node) in nodes.iter().enumerate() {
+            for (arg, child) in node.children.iter().enumerate() {
+                write!(dot, "  n{i} -> c{child} [lhead=cluster_{child}").unwrap();
+                if node.children.len() > 1 {
+                    write!(dot, ", label={arg}").unwrap();
+                }
+                writeln!(dot, "];").unwrap();
+            }
+        }
+        writeln!(dot, "}}").unwrap();
+        Ok(dot)
+    }
+
+    /// The canonical e-class of `value`, if it has an eq-sort.
+    fn dot_class(&self, value: Value) -> Option<Id> {
+        let sort = self.get_sort(&value)?;
+        sort.is_eq_sort()
+            .then(|| self.find(Id::from(value.bits as usize)))
+    }
+
+    fn dot_leaf(&self, value: Value) -> Expr {
+        self.get_sort(&value).unwrap().make_expr(value)
+    }
+}
+
+/// Quote `s` as a DOT string. Only `"` and `\` need escaping there (a
+/// backslash would otherwise start a label escape such as `\l`), and line
+/// breaks are written as `\n`.
+fn dot_quote(s: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The e-classes reachable from `root` through the children of `nodes`.
+fn dot_reachable(nodes: &[DotNode], root: Option<Id>) -> BTreeSet<Id> {
+    let mut members: HashMap<Id, Vec<&DotNode>> = HashMap::default();
+    for node in nodes {
+        if let Some(class) = node.class {
+            members.entry(class).or_default().push(node);
+        }
+    }
+
+    let mut reachable = BTreeSet::new();
+    let mut todo: Vec<Id> = root.into_iter().collect();
+    while let Some(class) = todo.pop() {
+        if reachable.insert(class) {
+            for node in members.get(&class).into_iter().flatten() {
+                todo.extend(node.children.iter().copied());
+            }
+        }
+    }
+    reachable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_dot_root() {
+        let mut egraph = EGraph::default();
+        egraph
+            .parse_and_run_program(
+                "(datatype Math (Num i64) (Neg Math))
+                 (union (Neg (Num 1)) (Num 2))
+                 (Num 3)",
+            )
+            .unwrap();
+
+        let dot = egraph.to_dot(&[], None).unwrap();
+        assert!(dot.contains("label=\"Num 3\""));
+
+        let root = Expr::call("Num", [Expr::Lit(Literal::Int(2))]);
+        let dot = egraph.to_dot(&[], Some(&root)).unwrap();
+        assert!(dot.contains("label=\"Num 1\""));
+        assert!(dot.contains("label=\"Neg\""));
+        assert!(!dot.contains("label=\"Num 3\""));
+
+        let dot = egraph.to_dot(&["Neg".into()], Some(&root)).unwrap();
+        assert!(!dot.contains("label=\"Num 1\""));
+    }
+
+    #[test]
+    fn test_dot_quote() {
+        assert_eq!(dot_quote("Num 1"), "\"Num 1\"");
+        assert_eq!(dot_quote("Var \"x\""), "\"Var \\\"x\\\"\"");
+        assert_eq!(dot_quote("a\\b\nλ"), "\"a\\\\b\\nλ\"");
+    }
+
+    #[test]
+    fn test_export_dot_root_is_typechecked() {
+        let mut egraph = EGraph::default();
+        let program = "(datatype Math (Num i64) (Neg Math))
+                       (export-dot \"math.dot\" :root (Nope (Num 1)))";
+        let err = egraph.parse_and_run_program(program).unwrap_err();
+        assert!(err.to_string().contains("Nope"), "{err}");
+    }
+}
@@ -0,0 +1,175 @@
+use std::fmt::{self, Display};
+
+use crate::util::HashMap;
+use crate::*;
+
+/// A chain of terms, each equal to the previous one for the given reason.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    pub start: Expr,
+    pub steps: Vec<(Justification, Expr)>,
+}
+
+impl Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.start)?;
+        for (justification, term) in &self.steps {
+            write!(f, "\n= {term} ; by {justification}")?;
+        }
+        Ok(())
+    }
+}
+
+impl EGraph {
+    /// Start recording why e-classes are merged, so they can be explained by
+    /// [`EGraph::explain_equivalence`]. This is much lighter than
+    /// `enable_proofs`, but only explains unions made after it is called.
+    /// Programs turn it on with `(set-option enable_explanations 1)`.
+    pub fn enable_explanations(&mut self) {
+        self.unionfind.enable_explanations();
+    }
+
+    /// Explain why `e1` and `e2` are equal, as a chain of unions performed by
+    /// rules, actions and congruence.
+    ///
+    /// Terms are shown as they were first added to the e-graph. Congruence
+    /// steps are not broken down into explanations for the children.
+    pub fn explain_equivalence(&mut self, e1: &Expr, e2: &Expr) -> Result<Explanation, Error> {
+        let no_explanation = || Error::ExplainError(e1.clone(), e2.clone());
+        let (sort, v1) = self.eval_expr(e1, None, false)?;
+        let (_, v2) = self.eval_expr(e2, Some(sort.clone()), false)?;
+        if !sort.is_eq_sort() {
+            return if v1 == v2 {
+                Ok(Explanation {
+                    start: e1.clone(),
+                    steps: vec![],
+                })
+            } else {
+                Err(no_explanation())
+            };
+        }
+
+        let mut terms = HashMap::default();
+        let id1 = self.original_id(e1, &mut terms)?;
+        let id2 = self.original_id(e2, &mut terms)?;
+        let steps = self
+            .unionfind
+            .explain(id1, id2)
+            .ok_or_else(no_explanation)?;
+        Ok(Explanation {
+            start: self.id_term(id1, &mut terms),
+            steps: steps
+                .into_iter()
+                .map(|(_, to, justification)| (justification, self.id_term(to, &mut terms)))
+                .collect(),
+        })
+    }
+
+    /// The id that was created for `expr`. If `expr` was never added as is,
+    /// this is an id created for an e-node with the same function and
+    /// equivalent children, and failing that, the canonical id.
+    fn original_id(&mut self, expr: &Expr, terms: &mut HashMap<Id, Expr>) -> Result<Id, Error> {
+        let (_, value) = self.eval_expr(expr, None, false)?;
+        let root = self.find(Id::from(value.bits as usize));
+        let (function, args) = match expr {
+            Expr::Call(function, args) => (function, args),
+            _ => return Ok(root),
+        };
+        let candidates: Vec<Id> = self
+            .unionfind
+            .ids_with_origin(*function)
+            .iter()
+            .copied()
+            .filter(|&id| self.find(id) == root)
+            .collect();
+        if let Some(&id) = candidates
+            .iter()
+            .find(|&&id| &self.id_term(id, terms) == expr)
+        {
+            return Ok(id);
+        }
+
+        let args = args
+            .iter()
+            .map(|arg| Ok(self.eval_expr(arg, None, false)?.1))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let similar = candidates.into_iter().find(|&id| {
+            let (f, inputs) = self.unionfind.origin(id).unwrap();
+            let types = &self.functions[&f].schema.input;
+            types
+                .iter()
+                .zip(inputs)
+                .zip(&args)
+                .all(|((ty, input), arg)| {
+                    if ty.is_eq_sort() {
+                        self.find(Id::from(input.bits as usize))
+                            == self.find(Id::from(arg.bits as usize))
+                    } else {
+                        input == arg
+                    }
+                })
+        });
+        Ok(similar.unwrap_or(root))
+    }
+
+    /// The term `id` was created for.
+    fn id_term(&self, id: Id, terms: &mut HashMap<Id, Expr>) -> Expr {
+        if let Some(term) = terms.get(&id) {
+            return term.clone();
+        }
+        let term = match self.unionfind.origin(id) {
+            Some((function, inputs)) => {
+                let children: Vec<Expr> = inputs
+                    .iter()
+                    .map(|value| {
+                        let sort = self.get_sort(value).unwrap();
+                        if sort.is_eq_sort() {
+                            self.id_term(Id::from(value.bits as usize), terms)
+                        } else {
+                            sort.make_expr(*value)
+                        }
+                    })
+                    .collect();
+                Expr::call(function, children)
+            }
+            None => Expr::Var(format!("#{id}").into()),
+        };
+        terms.insert(id, term.clone());
+        term
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn explain_rules_and_actions() {
+        let mut egraph = EGraph::default();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (set-option enable_explanations 1)
+                (datatype Math (Num i64) (Add Math Math))
+                (rewrite (Add a b) (Add b a))
+                (let e (Add (Num 1) (Num 2)))
+                (run 1)
+                (union e (Num 3))"#,
+            )
+            .unwrap();
+
+        let num = |n: i64| Expr::call("Num", [Expr::lit(n)]);
+        let add = |a, b| Expr::call("Add", [a, b]);
+        let explanation = egraph
+            .explain_equivalence(&add(num(2), num(1)), &num(3))
+            .unwrap();
+        assert_eq!(explanation.start, add(num(2), num(1)));
+        let steps = &explanation.steps;
+        assert_eq!(steps.len(), 2);
+        assert!(matches!(steps[0].0, Justification::Rule(..)));
+        assert_eq!(steps[0].1, add(num(1), num(2)));
+        assert_eq!(steps[1], (Justification::Action, num(3)));
+
+        assert!(egraph.explain_equivalence(&num(1), &num(2)).is_err());
+    }
+}
@@ -0,0 +1,283 @@
+use std::fmt::Display;
+use std::rc::Rc;
+
+use super::{extractable_functions, Bindings, Cost, CostModel, Node};
+use crate::ast::Symbol;
+use crate::util::{HashMap, HashSet};
+use crate::{EGraph, Error, Expr, Function, Id, Value};
+
+/// The cheapest e-node found so far for an e-class, together with the DAGs
+/// chosen for its eq-sort children. The children are shared rather than
+/// copied, so every e-class reachable from `node` is found by walking
+/// `children`; `total` counts each of those classes once.
+pub(super) struct DagCost<'a> {
+    pub(super) total: Cost,
+    id: Id,
+    /// The cost of `node` alone.
+    cost: Cost,
+    node: Node<'a>,
+    children: Vec<Rc<DagCost<'a>>>,
+}
+
+impl<'a> DagCost<'a> {
+    /// The e-node chosen for every e-class in the DAG, with its own cost.
+    pub(super) fn classes(&self) -> HashMap<Id, (Cost, Node<'a>)> {
+        let mut classes = HashMap::default();
+        classes.insert(self.id, (self.cost, self.node));
+        walk(&self.children, |dag| {
+            if classes.contains_key(&dag.id) {
+                false
+            } else {
+                classes.insert(dag.id, (dag.cost, dag.node));
+                true
+            }
+        });
+        classes
+    }
+}
+
+/// Walk the DAGs under `roots` depth-first and in order, descending into a
+/// DAG only when `visit` returns true for it.
+fn walk<'a>(roots: &[Rc<DagCost<'a>>], mut visit: impl FnMut(&DagCost<'a>) -> bool) {
+    let mut stack: Vec<&DagCost<'a>> = roots.iter().rev().map(|dag| &**dag).collect();
+    while let Some(dag) = stack.pop() {
+        if visit(dag) {
+            stack.extend(dag.children.iter().rev().map(|dag| &**dag));
+        }
+    }
+}
+
+pub(crate) struct DagExtractor<'a> {
+    costs: HashMap<Id, Rc<DagCost<'a>>>,
+    ctors: Vec<Symbol>,
+    egraph: &'a EGraph,
+    cost_model: &'a dyn CostModel,
+}
+
+/// Displays an extracted DAG as `(let ((v0 e0) ...) e)`, or just `e` if
+/// nothing is shared.
+pub(crate) struct LetDisplay<'a>(pub &'a [(Symbol, Expr)], pub &'a Expr);
+
+impl<'a> Display for LetDisplay<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "{}", self.1);
+        }
+        write!(f, "(let (")?;
+        for (i, (name, expr)) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "({name} {expr})")?;
+        }
+        write!(f, ") {})", self.1)
+    }
+}
+
+impl EGraph {
+    /// Extract the cheapest term for `value` under DAG cost, where every
+    /// e-class used by the term is paid for once no matter how many times it
+    /// occurs.
+    ///
+    /// Returns the cost, the let-bindings for shared subterms in dependency
+    /// order, and the body, which refers to the bindings as variables.
+    pub fn extract_dag(&mut self, value: Value) -> Result<(Cost, Bindings, Expr), Error> {
+        let cost_model = self.cost_model.clone();
+        let extractor = DagExtractor::new(self, &*cost_model);
+        extractor.find_best(value)
+    }
+}
+
+impl<'a> DagExtractor<'a> {
+    pub(crate) fn new(egraph: &'a EGraph, cost_model: &'a dyn CostModel) -> Self {
+        let mut extractor = DagExtractor {
+            costs: HashMap::default(),
+            ctors: extractable_functions(egraph),
+            egraph,
+            cost_model,
+        };
+        extractor.find_costs();
+        extractor
+    }
+
+    fn find_best(&self, value: Value) -> Result<(Cost, Bindings, Expr), Error> {
+        let sort = self.egraph.get_sort(&value).unwrap();
+        if !sort.is_eq_sort() {
+            return Ok((0, vec![], sort.make_expr(value)));
+        }
+        let root = self.egraph.find(Id::from(value.bits as usize));
+        let dag = self
+            .best(root)
+            .ok_or_else(|| self.egraph.extraction_error(root))?;
+        let (bindings, expr) = let_bound(self.egraph, &dag.classes(), root);
+        Ok((dag.total, bindings, expr))
+    }
+
+    /// The cheapest DAG found for the (canonical) e-class `id`, if any.
+    pub(super) fn best(&self, id: Id) -> Option<&DagCost<'a>> {
+        self.costs.get(&id).map(|dag| &**dag)
+    }
+
+    /// The DAG cost of picking `node` for the class `id`, or `None` if some
+    /// child has no cost yet or `id` is reachable from the children (which
+    /// would make the extracted term cyclic).
+    ///
+    /// When two children extract a shared class differently, the earlier
+    /// child's choice wins. Everything reachable from an earlier child is then
+    /// extracted as that child would, so mixing the choices cannot make a
+    /// cycle.
+    fn node_dag_cost(&self, id: Id, function: &Function, node: Node<'a>) -> Option<DagCost<'a>> {
+        let types = &function.schema.input;
+        let mut children = vec![];
+        for (ty, value) in types.iter().zip(node.inputs) {
+            if ty.is_eq_sort() {
+                let child = self.egraph.find(Id::from(value.bits as usize));
+                children.push(self.costs.get(&child)?.clone());
+            }
+        }
+
+        let cost = own_cost(self.cost_model, function, node.inputs);
+        let mut total = cost;
+        let mut cyclic = false;
+        let mut seen = HashSet::default();
+        walk(&children, |dag| {
+            cyclic |= dag.id == id;
+            if cyclic || !seen.insert(dag.id) {
+                return false;
+            }
+            total = total.saturating_add(dag.cost);
+            true
+        });
+        if cyclic {
+            return None;
+        }
+        Some(DagCost {
+            total,
+            id,
+            cost,
+            node,
+            children,
+        })
+    }
+
+    fn find_costs(&mut self) {
+        let mut did_something = true;
+        while did_something {
+            did_something = false;
+
+            for &sym in &self.ctors {
+                let func = &self.egraph.functions[&sym];
+                for (inputs, output) in func.nodes.iter() {
+                    let id = self.egraph.find(Id::from(output.value.bits as usize));
+                    let node = Node { sym, inputs };
+                    if let Some(new_cost) = self.node_dag_cost(id, func, node) {
+                        let better = match self.costs.get(&id) {
+                            Some(old) => new_cost.total < old.total,
+                            None => true,
+                        };
+                        if better {
+                            did_something = true;
+                            self.costs.insert(id, Rc::new(new_cost));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The cost of an e-node by itself. The e-classes of its children are paid
+/// for separately in a DAG, so they count as 0.
+pub(crate) fn own_cost(cost_model: &dyn CostModel, function: &Function, inputs: &[Value]) -> Cost {
+    let child_costs: Vec<Cost> = function
+        .schema
+        .input
+        .iter()
+        .zip(inputs)
+        .map(|(ty, value)| {
+            if ty.is_eq_sort() {
+                0
+            } else {
+                cost_model.leaf_cost(*value)
+            }
+        })
+        .collect();
+    cost_model.node_cost(&function.decl, inputs, &child_costs)
+}
+
+/// Turn the DAG rooted at `root`, given by the e-node chosen for every
+/// reachable e-class, into let-bindings and a body. A class is let-bound when
+/// more than one e-node in the DAG uses it.
+pub(super) fn let_bound(
+    egraph: &EGraph,
+    classes: &HashMap<Id, (Cost, Node)>,
+    root: Id,
+) -> (Bindings, Expr) {
+    let mut uses: HashMap<Id, usize> = HashMap::default();
+    for (_cost, node) in classes.values() {
+        let function = &egraph.functions[&node.sym];
+        for (ty, value) in function.schema.input.iter().zip(node.inputs) {
+            if ty.is_eq_sort() {
+                let child = egraph.find(Id::from(value.bits as usize));
+                *uses.entry(child).or_default() += 1;
+            }
+        }
+    }
+
+    let mut builder = LetBuilder {
+        egraph,
+        classes,
+        uses,
+        names: HashMap::default(),
+        bindings: vec![],
+    };
+    let expr = builder.build(root);
+    (builder.bindings, expr)
+}
+
+struct LetBuilder<'a, 'b> {
+    egraph: &'b EGraph,
+    classes: &'b HashMap<Id, (Cost, Node<'a>)>,
+    uses: HashMap<Id, usize>,
+    names: HashMap<Id, Symbol>,
+    bindings: Bindings,
+}
+
+impl<'a, 'b> LetBuilder<'a, 'b> {
+    fn build(&mut self, id: Id) -> Expr {
+        let node = self.classes[&id].1;
+        let egraph = self.egraph;
+        let function = &egraph.functions[&node.sym];
+        let children: Vec<Expr> = function
+            .schema
+            .input
+            .iter()
+            .zip(node.inputs)
+            .map(|(ty, &value)| {
+                if ty.is_eq_sort() {
+                    let child = egraph.find(Id::from(value.bits as usize));
+                    if self.uses[&child] > 1 {
+                        Expr::Var(self.bind(child))
+                    } else {
+                        self.build(child)
+                    }
+                } else {
+                    ty.make_expr(value)
+                }
+            })
+            .collect();
+        Expr::call(node.sym, children)
+    }
+
+    fn bind(&mut self, id: Id) -> Symbol {
+        if let Some(name) = self.names.get(&id) {
+            return *name;
+        }
+        // build first so that the bindings it depends on come before it
+        let expr = self.build(id);
+        let name = Symbol::from(format!("v{}", self.bindings.len()));
+        self.bindings.push((name, expr));
+        self.names.insert(id, name);
+        name
+    }
+}
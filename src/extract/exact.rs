@@ -0,0 +1,310 @@
+use instant::{Duration, Instant};
+
+use super::dag::{let_bound, own_cost, DagExtractor};
+use super::{extractable_functions, Bindings, Cost, CostModel, Node};
+use crate::util::{HashMap, HashSet};
+use crate::{EGraph, Error, Expr, Id, Value};
+
+/// An e-node that exact extraction may choose for its e-class.
+struct Candidate<'a> {
+    node: Node<'a>,
+    cost: Cost,
+    children: Vec<Id>,
+}
+
+/// Finds a term with minimum DAG cost by branch and bound.
+///
+/// The search decides one required e-class at a time, trying its e-nodes
+/// cheapest first. A partial solution is pruned as soon as its cost plus the
+/// cheapest e-node of every e-class it still requires reaches the cost of the
+/// best solution so far, which starts out as the greedy DAG extraction.
+struct ExactExtractor<'a> {
+    /// The usable e-nodes of every extractable e-class, cheapest first.
+    candidates: HashMap<Id, Vec<Candidate<'a>>>,
+    /// The candidate picked for each e-class decided so far.
+    chosen: HashMap<Id, usize>,
+    cost: Cost,
+    best: Option<(Cost, HashMap<Id, usize>)>,
+    deadline: Instant,
+    timed_out: bool,
+}
+
+/// How long exact extraction searches when no time limit is given.
+const DEFAULT_TIME_LIMIT: Duration = Duration::from_secs(10);
+
+impl EGraph {
+    /// Extract a term for `value` with minimum DAG cost, as defined by
+    /// [`EGraph::extract_dag`].
+    ///
+    /// The search gives up once `time_limit` (ten seconds if none is given)
+    /// has passed, returning the best term found so far (at worst the greedy
+    /// DAG extraction). The returned flag is true if the search finished, so
+    /// the term is proven optimal.
+    pub fn extract_exact(
+        &mut self,
+        value: Value,
+        time_limit: Option<Duration>,
+    ) -> Result<(Cost, Bindings, Expr, bool), Error> {
+        let egraph: &EGraph = self;
+        let sort = egraph.get_sort(&value).unwrap();
+        if !sort.is_eq_sort() {
+            return Ok((0, vec![], sort.make_expr(value), true));
+        }
+        let root = egraph.find(Id::from(value.bits as usize));
+        let cost_model = egraph.cost_model.clone();
+        let deadline = Instant::now() + time_limit.unwrap_or(DEFAULT_TIME_LIMIT);
+
+        let mut extractor = ExactExtractor::new(egraph, &*cost_model, deadline);
+        let greedy = DagExtractor::new(egraph, &*cost_model);
+        if let Some(dag) = greedy.best(root) {
+            let chosen = dag
+                .classes()
+                .iter()
+                .map(|(&id, (_cost, node))| {
+                    let i = extractor.candidate_index(id, node);
+                    Ok((id, i.ok_or_else(|| egraph.extraction_error(id))?))
+                })
+                .collect::<Result<_, Error>>()?;
+            extractor.best = Some((dag.total, chosen));
+        }
+
+        extractor.search(root);
+
+        let (cost, chosen) = extractor
+            .best
+            .as_ref()
+            .ok_or_else(|| egraph.extraction_error(root))?;
+        let classes = chosen
+            .iter()
+            .map(|(&id, &i)| {
+                let candidate = &extractor.candidates[&id][i];
+                (id, (candidate.cost, candidate.node))
+            })
+            .collect();
+        let (bindings, expr) = let_bound(egraph, &classes, root);
+        Ok((*cost, bindings, expr, !extractor.timed_out))
+    }
+}
+
+impl<'a> ExactExtractor<'a> {
+    fn new(egraph: &'a EGraph, cost_model: &dyn CostModel, deadline: Instant) -> Self {
+        let mut candidates: HashMap<Id, Vec<Candidate<'a>>> = HashMap::default();
+        for sym in extractable_functions(egraph) {
+            let function = &egraph.functions[&sym];
+            for (inputs, output) in function.nodes.iter() {
+                let id = egraph.find(Id::from(output.value.bits as usize));
+                let children = function
+                    .schema
+                    .input
+                    .iter()
+                    .zip(inputs)
+                    .filter(|(ty, _)| ty.is_eq_sort())
+                    .map(|(_, value)| egraph.find(Id::from(value.bits as usize)))
+                    .collect();
+                candidates.entry(id).or_default().push(Candidate {
+                    node: Node { sym, inputs },
+                    cost: own_cost(cost_model, function, inputs),
+                    children,
+                });
+            }
+        }
+
+        // Drop e-nodes that can never be part of a finite term, so that every
+        // class a candidate refers to has a candidate of its own.
+        let mut extractable: HashSet<Id> = HashSet::default();
+        let mut did_something = true;
+        while did_something {
+            did_something = false;
+            for (id, nodes) in &candidates {
+                if !extractable.contains(id)
+                    && nodes
+                        .iter()
+                        .any(|c| c.children.iter().all(|c| extractable.contains(c)))
+                {
+                    extractable.insert(*id);
+                    did_something = true;
+                }
+            }
+        }
+        candidates.retain(|id, _| extractable.contains(id));
+        for nodes in candidates.values_mut() {
+            nodes.retain(|c| c.children.iter().all(|c| extractable.contains(c)));
+            nodes.sort_by_key(|c| c.cost);
+        }
+
+        ExactExtractor {
+            candidates,
+            chosen: HashMap::default(),
+            cost: 0,
+            best: None,
+            deadline,
+            timed_out: false,
+        }
+    }
+
+    fn candidate_index(&self, id: Id, node: &Node) -> Option<usize> {
+        self.candidates
+            .get(&id)?
+            .iter()
+            .position(|c| &c.node == node)
+    }
+
+    fn out_of_time(&mut self) -> bool {
+        if !self.timed_out && Instant::now() >= self.deadline {
+            log::warn!("Exact extraction ran out of time");
+            self.timed_out = true;
+        }
+        self.timed_out
+    }
+
+    /// Whether `target` is reachable from `classes` through the e-nodes
+    /// chosen so far.
+    fn reaches(&self, classes: &[Id], target: Id) -> bool {
+        let mut seen: HashSet<Id> = HashSet::default();
+        let mut stack = classes.to_vec();
+        while let Some(id) = stack.pop() {
+            if id == target {
+                return true;
+            }
+            if let Some(&i) = self.chosen.get(&id) {
+                if seen.insert(id) {
+                    stack.extend(&self.candidates[&id][i].children);
+                }
+            }
+        }
+        false
+    }
+
+    /// A lower bound on the cost of deciding the classes in `todo`: each
+    /// undecided one needs at least its cheapest e-node.
+    fn remaining_bound(&self, todo: &[Id], except: Id) -> Cost {
+        let undecided: HashSet<Id> = todo
+            .iter()
+            .copied()
+            .filter(|id| *id != except && !self.chosen.contains_key(id))
+            .collect();
+        undecided
+            .iter()
+            .fold(0, |acc, id| acc.saturating_add(self.candidates[id][0].cost))
+    }
+
+    /// The first candidate for `id` from `start` on that could still beat the
+    /// best solution, given a lower bound on the rest of the partial
+    /// solution, and that keeps the term acyclic.
+    fn next_candidate(&self, id: Id, start: usize, bound: Cost) -> Option<usize> {
+        for (i, candidate) in self.candidates[&id].iter().enumerate().skip(start) {
+            if let Some((best, _)) = &self.best {
+                // candidates are sorted, so the rest are no better
+                if bound.saturating_add(candidate.cost) >= *best {
+                    return None;
+                }
+            }
+            if !self.reaches(&candidate.children, id) {
+                return Some(i);
+            }
+        }
+        None
+    }
+
+    /// Search depth-first for the cheapest way to decide every class
+    /// reachable from `root`. The classes being decided are kept on an
+    /// explicit stack, since a term can be much deeper than the call stack.
+    fn search(&mut self, root: Id) {
+        let mut todo = vec![root];
+        let mut frames: Vec<Frame> = vec![];
+        loop {
+            // take classes off the to-do list until one needs deciding
+            let complete = loop {
+                match todo.pop() {
+                    None => break true,
+                    Some(id) if self.chosen.contains_key(&id) => frames.push(Frame::Decided(id)),
+                    Some(id) => {
+                        let bound = self.cost.saturating_add(self.remaining_bound(&todo, id));
+                        frames.push(Frame::Deciding {
+                            id,
+                            next: 0,
+                            bound,
+                            cost: self.cost,
+                            len: todo.len(),
+                        });
+                        break false;
+                    }
+                }
+            };
+            if complete
+                && self
+                    .best
+                    .as_ref()
+                    .map_or(true, |(best, _)| self.cost < *best)
+            {
+                self.best = Some((self.cost, self.chosen.clone()));
+            }
+
+            if !self.next_choice(&mut todo, &mut frames) {
+                return;
+            }
+        }
+    }
+
+    /// Undo the innermost choices until a class being decided has another
+    /// candidate worth trying, and pick that candidate. Returns false when
+    /// the search is over.
+    fn next_choice(&mut self, todo: &mut Vec<Id>, frames: &mut Vec<Frame>) -> bool {
+        while let Some(frame) = frames.last_mut() {
+            match frame {
+                Frame::Decided(id) => todo.push(*id),
+                Frame::Deciding {
+                    id,
+                    next,
+                    bound,
+                    cost,
+                    len,
+                } => {
+                    let id = *id;
+                    if let Some(i) = self.chosen.remove(&id) {
+                        todo.truncate(*len);
+                        self.cost = *cost;
+                    }
+                    if !self.out_of_time() {
+                        if let Some(i) = self.next_candidate(id, *next, *bound) {
+                            *next = i + 1;
+                            let candidate = &self.candidates[&id][i];
+                            self.chosen.insert(id, i);
+                            self.cost = cost.saturating_add(candidate.cost);
+                            let chosen = &self.chosen;
+                            todo.extend(
+                                candidate
+                                    .children
+                                    .iter()
+                                    .filter(|c| !chosen.contains_key(*c)),
+                            );
+                            return true;
+                        }
+                    }
+                    todo.push(id);
+                }
+            }
+            frames.pop();
+        }
+        false
+    }
+}
+
+/// A class taken off the to-do list during the search, to be put back when
+/// the search backtracks past it.
+enum Frame {
+    /// The class had already been decided.
+    Decided(Id),
+    /// The class is being decided. `next` is the next candidate to try,
+    /// `bound` a lower bound on the cost of the partial solution without the
+    /// class, `cost` the cost of the partial solution before the class was
+    /// decided, and `len` the length of the to-do list before the chosen
+    /// candidate's children were added.
+    Deciding {
+        id: Id,
+        next: usize,
+        bound: Cost,
+        cost: Cost,
+        len: usize,
+    },
+}
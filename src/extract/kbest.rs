@@ -0,0 +1,233 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use super::{extractable_functions, Cost, CostModel, Node};
+use crate::util::{HashMap, HashSet};
+use crate::{EGraph, Expr, Id, Value};
+
+/// Finds the `k` cheapest distinct terms (by tree cost) of an e-class.
+///
+/// Like the single-best extractor this iterates to a fixpoint, but every
+/// e-class keeps a sorted list of up to `k` terms instead of one, and each
+/// e-node contributes the `k` cheapest combinations of its children's lists.
+/// Only the e-classes reachable from the root are considered.
+struct KBestExtractor<'a> {
+    k: usize,
+    terms: HashMap<Id, Vec<(Cost, Expr)>>,
+    nodes: Vec<(Id, Node<'a>)>,
+    egraph: &'a EGraph,
+    cost_model: &'a dyn CostModel,
+}
+
+impl EGraph {
+    /// The `limit` cheapest distinct terms for `value` and their costs,
+    /// cheapest first.
+    pub fn extract_variants(&mut self, value: Value, limit: usize) -> Vec<(Cost, Expr)> {
+        if limit == 0 {
+            return vec![];
+        }
+        let sort = self.get_sort(&value).unwrap();
+        if !sort.is_eq_sort() {
+            return vec![(0, sort.make_expr(value))];
+        }
+        let root = self.find(Id::from(value.bits as usize));
+        let cost_model = self.cost_model.clone();
+        let mut extractor = KBestExtractor::new(self, &*cost_model, limit, root);
+        extractor.terms.remove(&root).unwrap_or_default()
+    }
+}
+
+impl<'a> KBestExtractor<'a> {
+    fn new(egraph: &'a EGraph, cost_model: &'a dyn CostModel, k: usize, root: Id) -> Self {
+        let mut by_class: HashMap<Id, Vec<Node<'a>>> = HashMap::default();
+        for sym in extractable_functions(egraph) {
+            let function = &egraph.functions[&sym];
+            for (inputs, output) in function.nodes.iter() {
+                let id = egraph.find(Id::from(output.value.bits as usize));
+                by_class.entry(id).or_default().push(Node { sym, inputs });
+            }
+        }
+
+        let mut nodes = vec![];
+        let mut seen: HashSet<Id> = HashSet::default();
+        let mut stack = vec![root];
+        while let Some(id) = stack.pop() {
+            if !seen.insert(id) {
+                continue;
+            }
+            for &node in by_class.get(&id).into_iter().flatten() {
+                let function = &egraph.functions[&node.sym];
+                for (ty, value) in function.schema.input.iter().zip(node.inputs) {
+                    if ty.is_eq_sort() {
+                        stack.push(egraph.find(Id::from(value.bits as usize)));
+                    }
+                }
+                nodes.push((id, node));
+            }
+        }
+
+        let mut extractor = KBestExtractor {
+            k,
+            terms: HashMap::default(),
+            nodes,
+            egraph,
+            cost_model,
+        };
+        extractor.find_terms();
+        extractor
+    }
+
+    /// The eq-sort children of `node`.
+    fn children<'b>(&'b self, node: &'b Node) -> impl Iterator<Item = Id> + 'b {
+        let function = &self.egraph.functions[&node.sym];
+        function
+            .schema
+            .input
+            .iter()
+            .zip(node.inputs)
+            .filter(|(ty, _)| ty.is_eq_sort())
+            .map(|(_, value)| self.egraph.find(Id::from(value.bits as usize)))
+    }
+
+    /// The `k` cheapest terms rooted at `node` that cost less than `limit`,
+    /// cheapest first, given the terms found so far for its children.
+    fn node_terms(&self, node: &Node, limit: Option<Cost>) -> Vec<(Cost, Expr)> {
+        let function = &self.egraph.functions[&node.sym];
+        let types = &function.schema.input;
+
+        let mut lists = vec![];
+        for child in self.children(node) {
+            match self.terms.get(&child) {
+                Some(list) => lists.push(list),
+                None => return vec![],
+            }
+        }
+
+        // the cost of picking the `choice[i]`th term of each child
+        let cost = |choice: &[usize]| {
+            let mut choice = choice.iter().zip(&lists);
+            let child_costs: Vec<Cost> = types
+                .iter()
+                .zip(node.inputs)
+                .map(|(ty, value)| {
+                    if ty.is_eq_sort() {
+                        let (&i, list) = choice.next().unwrap();
+                        list[i].0
+                    } else {
+                        self.cost_model.leaf_cost(*value)
+                    }
+                })
+                .collect();
+            self.cost_model
+                .node_cost(&function.decl, node.inputs, &child_costs)
+        };
+        // the term for the same choice, only built once it is picked
+        let build = |choice: &[usize]| {
+            let mut choice = choice.iter().zip(&lists);
+            let children: Vec<Expr> = types
+                .iter()
+                .zip(node.inputs)
+                .map(|(ty, value)| {
+                    if ty.is_eq_sort() {
+                        let (&i, list) = choice.next().unwrap();
+                        list[i].1.clone()
+                    } else {
+                        ty.make_expr(*value)
+                    }
+                })
+                .collect();
+            Expr::call(node.sym, children)
+        };
+
+        // Enumerate choices cheapest first: every successor of a choice picks
+        // the next term for one of the children.
+        let mut result = vec![];
+        let mut queue = BinaryHeap::new();
+        let mut queued: HashSet<Vec<usize>> = HashSet::default();
+        let start = vec![0; lists.len()];
+        queue.push(Reverse((cost(&start), start.clone())));
+        queued.insert(start);
+        while let Some(Reverse((c, choice))) = queue.pop() {
+            if limit.map_or(false, |limit| c >= limit) {
+                break;
+            }
+            result.push((c, build(&choice)));
+            if result.len() == self.k {
+                break;
+            }
+            for i in 0..choice.len() {
+                if choice[i] + 1 < lists[i].len() {
+                    let mut next = choice.clone();
+                    next[i] += 1;
+                    if queued.insert(next.clone()) {
+                        queue.push(Reverse((cost(&next), next)));
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// Iterate to a fixpoint, only revisiting e-nodes with a child whose list
+    /// changed in the previous round.
+    ///
+    /// Under a monotone cost model, one of the `k` cheapest terms of a class
+    /// never repeats a class more than `k` times along a path (the nested
+    /// occurrences would give `k` other terms that are no more expensive), so
+    /// it is at most `k` times the number of classes deep. Each round finds
+    /// the terms one level deeper than the last, so that many rounds are
+    /// enough, even when zero-cost cycles keep producing new terms of the
+    /// same cost.
+    fn find_terms(&mut self) {
+        let n_classes = self
+            .nodes
+            .iter()
+            .map(|(id, _)| *id)
+            .collect::<HashSet<_>>()
+            .len();
+        let max_rounds = self.k.saturating_mul(n_classes).saturating_add(1);
+
+        let mut changed: Option<HashSet<Id>> = None;
+        for _ in 0..max_rounds {
+            let mut now_changed: HashSet<Id> = HashSet::default();
+            for (id, node) in &self.nodes {
+                if let Some(changed) = &changed {
+                    if !self.children(node).any(|child| changed.contains(&child)) {
+                        continue;
+                    }
+                }
+                let limit = self
+                    .terms
+                    .get(id)
+                    .filter(|list| list.len() == self.k)
+                    .map(|list| list[self.k - 1].0);
+                for term in self.node_terms(node, limit) {
+                    let list = self.terms.entry(*id).or_default();
+                    if insert_term(list, self.k, term) {
+                        now_changed.insert(*id);
+                    }
+                }
+            }
+            if now_changed.is_empty() {
+                return;
+            }
+            changed = Some(now_changed);
+        }
+    }
+}
+
+/// Insert `term` into the sorted `list` of at most `k` terms, unless it is
+/// already there or no cheaper than all of them. Returns whether it was
+/// inserted.
+fn insert_term(list: &mut Vec<(Cost, Expr)>, k: usize, term: (Cost, Expr)) -> bool {
+    if list.len() == k && term.0 >= list[k - 1].0 {
+        return false;
+    }
+    if list.iter().any(|(_, expr)| *expr == term.1) {
+        return false;
+    }
+    let pos = list.partition_point(|(cost, _)| *cost <= term.0);
+    list.insert(pos, term);
+    list.truncate(k);
+    true
+}
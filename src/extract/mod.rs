@@ -0,0 +1,340 @@
+use std::mem;
+use std::rc::Rc;
+
+use crate::ast::{FunctionDecl, Symbol};
+use crate::util::{HashMap, HashSet};
+use crate::{EGraph, Error, Expr, Function, Id, Value};
+
+mod dag;
+mod exact;
+mod kbest;
+pub(crate) use dag::{own_cost, LetDisplay};
+
+pub type Cost = usize;
+
+/// Let-bindings for the shared subterms of an extracted DAG, in dependency
+/// order.
+pub type Bindings = Vec<(Symbol, Expr)>;
+
+/// Assigns costs to terms during extraction.
+///
+/// The extractor calls [`CostModel::node_cost`] once per candidate e-node,
+/// bottom-up, and keeps the cheapest e-node for every e-class.
+pub trait CostModel {
+    /// The total cost of an e-node of the function declared by `decl`.
+    ///
+    /// `inputs` are the (canonical) argument values of the e-node, and
+    /// `child_costs` holds one cost per argument: the best cost found so far
+    /// for the argument's e-class if it has an eq-sort, and the result of
+    /// [`CostModel::leaf_cost`] if it is a primitive.
+    ///
+    /// The cost should be at least the cost of every eq-sort child. Otherwise
+    /// an e-class can pick an e-node that contains the e-class itself, and
+    /// tree extraction fails with [`Error::ExtractionCycle`].
+    fn node_cost(&self, decl: &FunctionDecl, inputs: &[Value], child_costs: &[Cost]) -> Cost;
+
+    /// The cost of a primitive leaf value, such as an `i64` argument.
+    fn leaf_cost(&self, value: Value) -> Cost {
+        let _ = value;
+        1
+    }
+
+    /// Whether [`CostModel::node_cost`] never decreases when one of the child
+    /// costs increases. Tree extraction only finds the cheapest term under a
+    /// monotone cost model, so extraction reports are marked optimal only
+    /// when this returns true.
+    fn is_monotone(&self) -> bool {
+        false
+    }
+}
+
+/// The default cost model: every e-node costs its function's `:cost` (or 1 if
+/// it has none) plus the costs of its children, and every primitive leaf costs
+/// 1.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultCostModel;
+
+impl CostModel for DefaultCostModel {
+    fn node_cost(&self, decl: &FunctionDecl, _inputs: &[Value], child_costs: &[Cost]) -> Cost {
+        child_costs
+            .iter()
+            .fold(decl.cost.unwrap_or(1), |acc, &c| acc.saturating_add(c))
+    }
+
+    fn is_monotone(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Node<'a> {
+    sym: Symbol,
+    inputs: &'a [Value],
+}
+
+struct Extractor<'a> {
+    /// The cheapest e-node of every e-class, as its function and offset into
+    /// the function's table.
+    costs: HashMap<Id, (Cost, Symbol, usize)>,
+    ctors: Vec<Symbol>,
+    egraph: &'a EGraph,
+    cost_model: &'a dyn CostModel,
+}
+
+/// The costs found by the last [`EGraph::extract`], along with enough of the
+/// e-graph's state to tell what has changed since.
+#[derive(Clone, Default)]
+pub(crate) struct ExtractionCache {
+    costs: HashMap<Id, (Cost, Symbol, usize)>,
+    /// The generation and length of every extractable function's table.
+    tables: HashMap<Symbol, (usize, usize)>,
+    n_unions: usize,
+    n_ids: usize,
+}
+
+impl EGraph {
+    pub fn value_to_id(&self, value: Value) -> Option<(Symbol, Id)> {
+        if let Some(sort) = self.get_sort(&value) {
+            if sort.is_eq_sort() {
+                let id = Id::from(value.bits as usize);
+                return Some((sort.name(), self.find(id)));
+            }
+        }
+        None
+    }
+
+    /// Replace the cost model used by `extract`, `output`, `print` and
+    /// `simplify`.
+    pub fn set_cost_model(&mut self, cost_model: impl CostModel + 'static) {
+        self.cost_model = Rc::new(cost_model);
+        self.extraction_cache = Default::default();
+    }
+
+    /// Extract the cheapest term for `value` under the e-graph's cost model.
+    ///
+    /// The costs are cached between calls, so extracting again before the
+    /// e-graph changes is cheap, and after adding e-nodes only the new ones
+    /// are looked at. The cache is coarse: any union or removal, a `pop`, or
+    /// a new cost model throws it away and starts the search over, even if
+    /// the e-classes involved are unrelated to `value`. Only this method uses
+    /// it; [`EGraph::extract_with`] and the DAG, exact and k-best extractors
+    /// always start from scratch.
+    pub fn extract(&mut self, value: Value) -> Result<(Cost, Expr), Error> {
+        let cost_model = self.cost_model.clone();
+        let cache = mem::take(&mut self.extraction_cache);
+        let extractor = Extractor::from_cache(self, &*cost_model, cache);
+        let result = extractor.find_best(value);
+        self.extraction_cache = extractor.into_cache();
+        result
+    }
+
+    /// Like [`EGraph::extract`], but using the given cost model instead of the
+    /// e-graph's.
+    pub fn extract_with(
+        &mut self,
+        value: Value,
+        cost_model: &dyn CostModel,
+    ) -> Result<(Cost, Expr), Error> {
+        Extractor::new(self, cost_model).find_best(value)
+    }
+
+    /// The error for an e-class that has no finite-cost term, naming the
+    /// function of its oldest e-node (the one that introduced it).
+    fn extraction_error(&self, id: Id) -> Error {
+        let introduced_by = self
+            .functions
+            .iter()
+            .flat_map(|(&sym, func)| {
+                func.nodes
+                    .iter()
+                    .filter(|(_, output)| {
+                        self.get_sort(&output.value)
+                            .map_or(false, |sort| sort.is_eq_sort())
+                            && self.find(Id::from(output.value.bits as usize)) == id
+                    })
+                    .map(move |(_, output)| (output.timestamp, sym))
+            })
+            .min()
+            .map(|(_, sym)| sym);
+        Error::ExtractionError(id, introduced_by)
+    }
+}
+
+/// The functions whose e-nodes may appear in extracted terms: those that
+/// return an eq-sort and are not marked `:unextractable`.
+///
+/// A function returning a primitive has no e-class to stand for: the bits of
+/// its output are a number, not an [`Id`], and reading them as one would make
+/// its rows candidates for whichever e-class happens to share that number (or
+/// for one that does not exist).
+fn extractable_functions(egraph: &EGraph) -> Vec<Symbol> {
+    egraph
+        .functions
+        .iter()
+        .filter(|(_, func)| func.schema.output.is_eq_sort() && !func.decl.unextractable)
+        .map(|(&sym, _)| sym)
+        .collect()
+}
+
+impl<'a> Extractor<'a> {
+    fn new(egraph: &'a EGraph, cost_model: &'a dyn CostModel) -> Self {
+        let ctors = extractable_functions(egraph);
+        let rows: Vec<_> = ctors.iter().map(|&sym| (sym, 0)).collect();
+        let mut extractor = Extractor {
+            costs: HashMap::default(),
+            egraph,
+            ctors,
+            cost_model,
+        };
+
+        log::debug!("Extracting from ctors: {:?}", extractor.ctors);
+        extractor.find_costs(&rows);
+        extractor
+    }
+
+    /// Like [`Extractor::new`], but starting from the costs in `cache` when
+    /// no union, removal or update has happened since they were found.
+    fn from_cache(
+        egraph: &'a EGraph,
+        cost_model: &'a dyn CostModel,
+        cache: ExtractionCache,
+    ) -> Self {
+        let ctors = extractable_functions(egraph);
+        let unchanged = cache.n_unions == egraph.unionfind.n_unions()
+            && ctors.iter().all(|sym| {
+                cache.tables.get(sym).map_or(true, |&(generation, _)| {
+                    generation == egraph.functions[sym].nodes.generation()
+                })
+            });
+        if !unchanged {
+            return Self::new(egraph, cost_model);
+        }
+
+        let new_rows: Vec<_> = ctors
+            .iter()
+            .map(|&sym| (sym, cache.tables.get(&sym).map_or(0, |&(_, len)| len)))
+            .collect();
+        let all_rows: Vec<_> = ctors.iter().map(|&sym| (sym, 0)).collect();
+        let mut extractor = Extractor {
+            costs: cache.costs,
+            egraph,
+            ctors,
+            cost_model,
+        };
+
+        // The old e-nodes only refer to e-classes that already existed, so
+        // they need another look only if one of those got cheaper.
+        let improved = extractor.find_costs(&new_rows);
+        if improved.iter().any(|&id| usize::from(id) < cache.n_ids) {
+            extractor.find_costs(&all_rows);
+        }
+        extractor
+    }
+
+    fn into_cache(self) -> ExtractionCache {
+        let functions = &self.egraph.functions;
+        ExtractionCache {
+            tables: self
+                .ctors
+                .iter()
+                .map(|sym| {
+                    let nodes = &functions[sym].nodes;
+                    (*sym, (nodes.generation(), nodes.len()))
+                })
+                .collect(),
+            n_unions: self.egraph.unionfind.n_unions(),
+            n_ids: self.egraph.unionfind.size(),
+            costs: self.costs,
+        }
+    }
+
+    /// The term rooted at the e-node at `offset` in `sym`'s table, where
+    /// `visiting` holds the e-classes the term is already inside of.
+    fn expr_from_node(
+        &self,
+        sym: Symbol,
+        offset: usize,
+        visiting: &mut Vec<Id>,
+    ) -> Result<Expr, Error> {
+        let (inputs, _) = self.egraph.functions[&sym].nodes.get_index(offset).unwrap();
+        let children = inputs
+            .iter()
+            .map(|&value| Ok(self.best_term(value, visiting)?.1))
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(Expr::call(sym, children))
+    }
+
+    fn find_best(&self, value: Value) -> Result<(Cost, Expr), Error> {
+        self.best_term(value, &mut vec![])
+    }
+
+    /// Like [`Extractor::find_best`], but failing if the best e-node of an
+    /// e-class in `visiting` turns up again below it. That happens only when
+    /// the cost model makes an e-node cheaper than one of its children.
+    fn best_term(&self, value: Value, visiting: &mut Vec<Id>) -> Result<(Cost, Expr), Error> {
+        let sort = self.egraph.get_sort(&value).unwrap();
+        if sort.is_eq_sort() {
+            let id = self.egraph.find(Id::from(value.bits as usize));
+            let &(cost, sym, offset) = self
+                .costs
+                .get(&id)
+                .ok_or_else(|| self.egraph.extraction_error(id))?;
+            if visiting.contains(&id) {
+                return Err(Error::ExtractionCycle(id));
+            }
+            visiting.push(id);
+            let expr = self.expr_from_node(sym, offset, visiting)?;
+            visiting.pop();
+            Ok((cost, expr))
+        } else {
+            Ok((0, sort.make_expr(value)))
+        }
+    }
+
+    fn node_total_cost(&self, function: &Function, children: &[Value]) -> Option<Cost> {
+        let types = &function.schema.input;
+        let mut child_costs = Vec::with_capacity(children.len());
+        for (ty, value) in types.iter().zip(children) {
+            child_costs.push(if ty.is_eq_sort() {
+                let id = self.egraph.find(Id::from(value.bits as usize));
+                // TODO costs should probably map values?
+                self.costs.get(&id)?.0
+            } else {
+                self.cost_model.leaf_cost(*value)
+            });
+        }
+        Some(
+            self.cost_model
+                .node_cost(&function.decl, children, &child_costs),
+        )
+    }
+
+    /// Iterate to a fixpoint over the rows of each function from the given
+    /// offset on, returning the e-classes whose cost went down.
+    fn find_costs(&mut self, rows: &[(Symbol, usize)]) -> HashSet<Id> {
+        let mut improved = HashSet::default();
+        let mut did_something = true;
+        while did_something {
+            did_something = false;
+
+            for &(sym, start) in rows {
+                let func = &self.egraph.functions[&sym];
+                for (offset, inputs, output) in func.nodes.iter_range(start..func.nodes.len()) {
+                    if let Some(new_cost) = self.node_total_cost(func, inputs) {
+                        let id = self.egraph.find(Id::from(output.value.bits as usize));
+                        if self
+                            .costs
+                            .get(&id)
+                            .map_or(true, |&(cost, ..)| new_cost < cost)
+                        {
+                            self.costs.insert(id, (new_cost, sym, offset));
+                            improved.insert(id);
+                            did_something = true;
+                        }
+                    }
+                }
+            }
+        }
+        improved
+    }
+}
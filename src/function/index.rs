@@ -33,6 +33,15 @@ impl ColumnIndex {
         self.ids.clear()
     }
 
+    /// Undo the last call to [`ColumnIndex::add`] for `v`.
+    pub(crate) fn remove_last(&mut self, v: Value) {
+        let offsets = self.ids.get_mut(&v.bits).unwrap();
+        offsets.pop();
+        if offsets.is_empty() {
+            self.ids.remove(&v.bits);
+        }
+    }
+
     pub(crate) fn len(&self) -> usize {
         self.ids.len()
     }
@@ -94,6 +103,12 @@ impl CompositeColumnIndex {
         }
     }
 
+    /// Undo the last call to [`CompositeColumnIndex::add`] for `v`.
+    pub(crate) fn remove_last(&mut self, v: Value) {
+        let index = self.0.iter_mut().find(|index| index.sort() == v.tag);
+        index.unwrap().remove_last(v);
+    }
+
     pub(crate) fn iter(&self) -> impl Iterator<Item = &ColumnIndex> {
         self.0.iter()
     }
@@ -21,6 +21,31 @@ pub struct Function {
     index_updated_through: usize,
     updates: usize,
     scratch: IndexSet<usize>,
+    /// Index entries added while there is a checkpoint. The table logs its
+    /// own changes.
+    index_undo: Vec<IndexUndo>,
+    checkpoints: Vec<Checkpoint>,
+}
+
+/// An index change, with what is needed to revert it.
+#[derive(Clone)]
+enum IndexUndo {
+    /// The value's entry in the index for the column got a new offset.
+    Add(usize, Value),
+    /// Likewise, for the rebuild index of the column.
+    AddRebuild(usize, Value),
+    /// The indexes were cleared; these were their contents.
+    Reset {
+        indexes: Vec<Rc<ColumnIndex>>,
+        rebuild_indexes: Vec<Option<CompositeColumnIndex>>,
+    },
+}
+
+#[derive(Clone)]
+struct Checkpoint {
+    index_undo_len: usize,
+    index_updated_through: usize,
+    updates: usize,
 }
 
 #[derive(Clone)]
@@ -138,6 +163,8 @@ impl Function {
             rebuild_indexes,
             index_updated_through: 0,
             updates: 0,
+            index_undo: vec![],
+            checkpoints: vec![],
             merge: MergeAction {
                 on_merge,
                 merge_vals,
@@ -150,6 +177,7 @@ impl Function {
         self.insert_internal(inputs, value, timestamp, true)
     }
     pub fn clear(&mut self) {
+        self.save_indexes();
         self.nodes.clear();
         self.indexes
             .iter_mut()
@@ -217,7 +245,72 @@ impl Function {
         mem::take(&mut self.updates)
     }
 
+    /// Log changes to the table and its indexes from now on.
+    pub(crate) fn checkpoint(&mut self) {
+        self.nodes.checkpoint();
+        self.checkpoints.push(Checkpoint {
+            index_undo_len: self.index_undo.len(),
+            index_updated_through: self.index_updated_through,
+            updates: self.updates,
+        });
+    }
+
+    /// Put the table and indexes back as they were at the last checkpoint.
+    /// Returns false if there was none, i.e. the function was declared after
+    /// it.
+    pub(crate) fn rollback(&mut self) -> bool {
+        let checkpoint = match self.checkpoints.pop() {
+            Some(checkpoint) => checkpoint,
+            None => return false,
+        };
+        self.nodes.rollback();
+        while self.index_undo.len() > checkpoint.index_undo_len {
+            match self.index_undo.pop().unwrap() {
+                IndexUndo::Add(col, value) => {
+                    Rc::make_mut(&mut self.indexes[col]).remove_last(value)
+                }
+                IndexUndo::AddRebuild(col, value) => self.rebuild_indexes[col]
+                    .as_mut()
+                    .unwrap()
+                    .remove_last(value),
+                IndexUndo::Reset {
+                    indexes,
+                    rebuild_indexes,
+                } => {
+                    self.indexes = indexes;
+                    self.rebuild_indexes = rebuild_indexes;
+                }
+            }
+        }
+        self.index_updated_through = checkpoint.index_updated_through;
+        self.updates = checkpoint.updates;
+        true
+    }
+
+    /// Drop the last checkpoint of the table and indexes, keeping the changes
+    /// made since.
+    pub(crate) fn commit(&mut self) {
+        if self.checkpoints.pop().is_some() {
+            self.nodes.commit();
+        }
+        if self.checkpoints.is_empty() {
+            self.index_undo.clear();
+        }
+    }
+
+    /// Before clearing the indexes, save them if they may need to be restored.
+    fn save_indexes(&mut self) {
+        if !self.checkpoints.is_empty() {
+            self.index_undo.push(IndexUndo::Reset {
+                indexes: self.indexes.clone(),
+                rebuild_indexes: self.rebuild_indexes.clone(),
+            });
+        }
+    }
+
     fn build_indexes(&mut self, offsets: Range<usize>) {
+        let logging = !self.checkpoints.is_empty();
+        let undo = &mut self.index_undo;
         for (col, (index, rebuild_index)) in self
             .indexes
             .iter_mut()
@@ -225,13 +318,19 @@ impl Function {
             .enumerate()
         {
             let as_mut = Rc::make_mut(index);
+            let mut add = |value, slot| {
+                as_mut.add(value, slot);
+                if logging {
+                    undo.push(IndexUndo::Add(col, value));
+                }
+            };
             if col == self.schema.input.len() {
                 for (slot, _, out) in self.nodes.iter_range(offsets.clone()) {
-                    as_mut.add(out.value, slot)
+                    add(out.value, slot)
                 }
             } else {
                 for (slot, inp, _) in self.nodes.iter_range(offsets.clone()) {
-                    as_mut.add(inp[col], slot)
+                    add(inp[col], slot)
                 }
             }
 
@@ -241,14 +340,24 @@ impl Function {
                     for (slot, _, out) in self.nodes.iter_range(offsets.clone()) {
                         self.schema.output.foreach_tracked_values(
                             &out.value,
-                            Box::new(|value| rebuild_index.add(value, slot)),
+                            Box::new(|value| {
+                                rebuild_index.add(value, slot);
+                                if logging {
+                                    undo.push(IndexUndo::AddRebuild(col, value));
+                                }
+                            }),
                         )
                     }
                 } else {
                     for (slot, inp, _) in self.nodes.iter_range(offsets.clone()) {
                         self.schema.input[col].foreach_tracked_values(
                             &inp[col],
-                            Box::new(|value| rebuild_index.add(value, slot)),
+                            Box::new(|value| {
+                                rebuild_index.add(value, slot);
+                                if logging {
+                                    undo.push(IndexUndo::AddRebuild(col, value));
+                                }
+                            }),
                         )
                     }
                 }
@@ -265,6 +374,7 @@ impl Function {
         if !self.nodes.too_stale() {
             return;
         }
+        self.save_indexes();
 
         for index in &mut self.indexes {
             // Everything works if we don't have a unique copy of the indexes,
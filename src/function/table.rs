@@ -19,6 +19,10 @@
 //! making it efficient to iterate over subsets of a table matching a given
 //! timestamp range.
 //!
+//! Tables can also record an undo log, so that all changes made since a
+//! checkpoint can be rolled back in time proportional to their number. This
+//! backs `push` and `pop`.
+//!
 //! Note on rehashing: We will eventually want to keep old/stale entries around
 //! to facilitate proofs/provenance. Early testing found that removing this in
 //! the "obvious" way (keeping 'vals' around, avoiding `mem::take()`s for stale
@@ -50,8 +54,41 @@ struct TableOffset {
 pub(crate) struct Table {
     max_ts: u32,
     n_stale: usize,
+    /// Bumped whenever an existing entry is removed, updated or moved, i.e.
+    /// on any change other than appending a new entry.
+    generation: usize,
     table: RawTable<TableOffset>,
     vals: Vec<(Input, TupleOutput)>,
+    /// Entries appended, updated, removed or marked stale while there is a
+    /// checkpoint.
+    undo: Vec<Undo>,
+    checkpoints: Vec<Checkpoint>,
+}
+
+/// An entry change, with what is needed to revert it.
+#[derive(Clone)]
+enum Undo {
+    /// A new entry was appended.
+    Append,
+    /// The entry at the offset was updated: its key moved to a new entry at
+    /// the end of the table.
+    Update(Offset),
+    /// The entry at the offset was removed from the table.
+    Remove(Offset),
+    /// The entry at the offset was marked stale (but is still in `table`).
+    Stale(Offset),
+    /// The table was rehashed or cleared; these were its contents.
+    Reset {
+        table: RawTable<TableOffset>,
+        vals: Vec<(Input, TupleOutput)>,
+    },
+}
+
+#[derive(Clone)]
+struct Checkpoint {
+    undo_len: usize,
+    max_ts: u32,
+    n_stale: usize,
 }
 
 /// Used for the RawTable probe sequence.
@@ -73,8 +110,10 @@ macro_rules! search_for {
 impl Table {
     /// Clear the contents of the table.
     pub(crate) fn clear(&mut self) {
+        self.save_contents();
         self.max_ts = 0;
         self.n_stale = 0;
+        self.generation += 1;
         self.table.clear();
         self.vals.clear();
     }
@@ -86,6 +125,7 @@ impl Table {
 
     /// Rehashes the table, invalidating any offsets stored into the table.
     pub(crate) fn rehash(&mut self) {
+        self.save_contents();
         let mut src = 0usize;
         let mut dst = 0usize;
         self.table.clear();
@@ -103,6 +143,7 @@ impl Table {
             }
         });
         self.n_stale = 0;
+        self.generation += 1;
     }
 
     /// Get the entry in the table for the given values, if they are in the
@@ -151,8 +192,12 @@ impl Table {
             }
             inp.stale_at = ts;
             self.n_stale += 1;
+            self.generation += 1;
             let k = mem::take(&mut inp.data);
             let new_offset = self.vals.len();
+            if !self.checkpoints.is_empty() {
+                self.undo.push(Undo::Update(*off));
+            }
             self.vals.push((
                 Input::new(k),
                 TupleOutput {
@@ -179,6 +224,9 @@ impl Table {
             },
             |off| off.hash,
         );
+        if self.logging() {
+            self.undo.push(Undo::Append);
+        }
     }
 
     /// One more than the maximum (potentially) valid offset into the table.
@@ -186,6 +234,12 @@ impl Table {
         self.vals.len()
     }
 
+    /// A counter that changes whenever existing entries do. Offsets into the
+    /// table and the entries they point to stay the same as long as it does.
+    pub(crate) fn generation(&self) -> usize {
+        self.generation
+    }
+
     /// Whether the table is completely empty, including stale entries.
     pub(crate) fn is_empty(&self) -> bool {
         self.len() == 0
@@ -218,6 +272,10 @@ impl Table {
         };
         self.vals[entry.off].0.stale_at = ts;
         self.n_stale += 1;
+        self.generation += 1;
+        if self.logging() {
+            self.undo.push(Undo::Remove(entry.off));
+        }
         true
     }
 
@@ -227,6 +285,10 @@ impl Table {
         if inp.live() {
             inp.stale_at = ts;
             self.n_stale += 1;
+            self.generation += 1;
+            if self.logging() {
+                self.undo.push(Undo::Stale(i));
+            }
         }
     }
 
@@ -262,6 +324,81 @@ impl Table {
             })
     }
 
+    fn logging(&self) -> bool {
+        !self.checkpoints.is_empty()
+    }
+
+    /// Before a rehash or clear, save the contents of the table if they may
+    /// need to be restored. This is linear in the size of the table, like the
+    /// operations themselves.
+    fn save_contents(&mut self) {
+        if self.logging() {
+            self.undo.push(Undo::Reset {
+                table: self.table.clone(),
+                vals: self.vals.clone(),
+            });
+        }
+    }
+
+    /// Log changes to the entries from now on.
+    pub(crate) fn checkpoint(&mut self) {
+        self.checkpoints.push(Checkpoint {
+            undo_len: self.undo.len(),
+            max_ts: self.max_ts,
+            n_stale: self.n_stale,
+        });
+    }
+
+    /// Put the entries back as they were at the last checkpoint. Returns
+    /// false if there was none.
+    pub(crate) fn rollback(&mut self) -> bool {
+        let checkpoint = match self.checkpoints.pop() {
+            Some(checkpoint) => checkpoint,
+            None => return false,
+        };
+        while self.undo.len() > checkpoint.undo_len {
+            match self.undo.pop().unwrap() {
+                Undo::Append => {
+                    let off = self.vals.len() - 1;
+                    let (inp, _) = self.vals.pop().unwrap();
+                    let hash = hash_values(inp.data());
+                    self.table.remove_entry(hash, |to| to.off == off);
+                }
+                Undo::Update(old) => {
+                    let new = self.vals.len() - 1;
+                    let (inp, _) = self.vals.pop().unwrap();
+                    let hash = hash_values(inp.data());
+                    self.table.get_mut(hash, |to| to.off == new).unwrap().off = old;
+                    self.vals[old].0 = inp;
+                }
+                Undo::Remove(off) => {
+                    let inp = &mut self.vals[off].0;
+                    inp.stale_at = u32::MAX;
+                    let hash = hash_values(inp.data());
+                    self.table
+                        .insert(hash, TableOffset { hash, off }, |to| to.hash);
+                }
+                Undo::Stale(off) => self.vals[off].0.stale_at = u32::MAX,
+                Undo::Reset { table, vals } => {
+                    self.table = table;
+                    self.vals = vals;
+                }
+            }
+        }
+        self.max_ts = checkpoint.max_ts;
+        self.n_stale = checkpoint.n_stale;
+        self.generation += 1;
+        true
+    }
+
+    /// Drop the last checkpoint, keeping the changes made since.
+    pub(crate) fn commit(&mut self) {
+        self.checkpoints.pop();
+        if self.checkpoints.is_empty() {
+            self.undo.clear();
+        }
+    }
+
     #[cfg(debug_assertions)]
     pub(crate) fn assert_sorted(&self) {
         assert!(self
@@ -0,0 +1,290 @@
+use std::path::Path;
+
+use ordered_float::OrderedFloat;
+
+use crate::*;
+
+impl EGraph {
+    /// Add a row to the function `name` for every record in the file at
+    /// `path`, returning the number of rows.
+    ///
+    /// Each record holds the inputs of a row followed by its output, unless
+    /// the function returns `Unit`. Fields are parsed according to the
+    /// function's schema, and fields of an eq-sort are terms of that sort or
+    /// names of globals. If any record fails to parse or to be added,
+    /// no rows are added.
+    pub(crate) fn input_file(
+        &mut self,
+        name: Symbol,
+        path: &Path,
+        format: InputFormat,
+        header: bool,
+    ) -> Result<usize, Error> {
+        let func = self.functions.get(&name).ok_or(TypeError::Unbound(name))?;
+        let is_unit = func.schema.output.name() == UNIT_SYM.into();
+        let mut sorts = func.schema.input.clone();
+        if !is_unit {
+            sorts.push(func.schema.output.clone());
+        }
+
+        log::info!("Opening file '{:?}'...", path);
+        let contents = std::fs::read_to_string(path).map_err(|e| Error::IoError(path.into(), e))?;
+        let delimiter = match format {
+            InputFormat::Tsv => '\t',
+            InputFormat::Csv => ',',
+        };
+        let input_error = |line, message| Error::InputError(path.into(), line, message);
+        let records =
+            read_records(&contents, delimiter).map_err(|(line, m)| input_error(line, m))?;
+
+        // parse every record before adding any, so that a bad record leaves
+        // the function as it was
+        let mut rows = vec![];
+        for (line, fields) in records.into_iter().skip(header as usize) {
+            if fields.len() != sorts.len() {
+                let message = format!("expected {} fields, found {}", sorts.len(), fields.len());
+                return Err(input_error(line, message));
+            }
+            let mut exprs = (sorts.iter().zip(&fields))
+                .map(|(sort, field)| self.parse_field(sort, field))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|message| input_error(line, message))?;
+
+            let action = if is_unit {
+                Action::Expr(Expr::Call(name, exprs))
+            } else {
+                let out = exprs.pop().unwrap();
+                Action::Set(name, exprs, out)
+            };
+            rows.push((line, action));
+        }
+
+        // a record can also fail to be added, e.g. by conflicting with an
+        // earlier one, so undo the rows before it in that case
+        self.push();
+        for (line, action) in &rows {
+            if let Err(e) = self.eval_actions(std::slice::from_ref(action)) {
+                self.pop().unwrap();
+                return Err(input_error(*line, e.to_string()));
+            }
+        }
+        self.commit();
+        Ok(rows.len())
+    }
+
+    fn parse_field(&self, sort: &ArcSort, field: &str) -> Result<Expr, String> {
+        if sort.is_eq_sort() {
+            // a term, as written by `output-table`, or the name of a global
+            let expr = match (self.proof_state.desugar).parse_action(&format!("(let x {field})")) {
+                Ok(Action::Let(_, Expr::Var(sym))) => match self.functions.get(&sym) {
+                    Some(f) if !f.is_variable && f.schema.input.is_empty() => Expr::call(sym, []),
+                    _ => Expr::Var(sym),
+                },
+                Ok(Action::Let(_, expr @ Expr::Call(..))) => expr,
+                _ => return Err(format!("invalid {} term {field:?}", sort.name())),
+            };
+            let types = Default::default();
+            self.compile_expr(&types, &expr, Some(sort.clone()))
+                .map_err(|errors| ListDisplay(&errors, "; ").to_string())?;
+            return Ok(expr);
+        }
+
+        let invalid = || format!("invalid {} {field:?}", sort.name());
+        let int = |s: &str| s.trim().parse::<i64>().map_err(|_| invalid());
+        let lit = match sort.name().as_str() {
+            "i64" => Literal::Int(int(field)?),
+            "f64" => Literal::F64(OrderedFloat(field.parse().map_err(|_| invalid())?)),
+            "String" => Literal::String(field.into()),
+            "Unit" if field.is_empty() || field == "()" => Literal::Unit,
+            "Unit" => return Err(invalid()),
+            "Rational" => {
+                let (numer, denom) = field.split_once('/').unwrap_or((field, "1"));
+                let (numer, denom) = (int(numer)?, int(denom)?);
+                if denom == 0 {
+                    return Err(invalid());
+                }
+                return Ok(Expr::call(
+                    "rational",
+                    [Literal::Int(numer), Literal::Int(denom)].map(Expr::Lit),
+                ));
+            }
+            s => return Err(format!("unsupported type {s} for input")),
+        };
+        Ok(Expr::Lit(lit))
+    }
+
+    /// Write every row of the function `name` to the file at `path` as
+    /// tab-separated values, in the format read by `input`, returning the
+    /// number of rows. Values of eq-sorts are written as extracted terms.
+    pub(crate) fn output_table(&mut self, name: Symbol, path: &Path) -> Result<usize, Error> {
+        self.rebuild()?;
+        let func = self.functions.get(&name).ok_or(TypeError::Unbound(name))?;
+        let is_unit = func.schema.output.name() == UNIT_SYM.into();
+        let rows: Vec<Vec<Value>> = (func.nodes.iter())
+            .map(|(inputs, output)| {
+                let mut row = inputs.to_vec();
+                if !is_unit {
+                    row.push(output.value);
+                }
+                row
+            })
+            .collect();
+
+        let mut contents = String::new();
+        for row in &rows {
+            for (i, &value) in row.iter().enumerate() {
+                if i > 0 {
+                    contents.push('\t');
+                }
+                let field = self.table_field(value)?;
+                if field.is_empty()
+                    || field.starts_with('"')
+                    || field.contains(['\t', '\n', '\r'])
+                    || field.trim() != field
+                {
+                    contents.push('"');
+                    contents.push_str(&field.replace('"', "\"\""));
+                    contents.push('"');
+                } else {
+                    contents.push_str(&field);
+                }
+            }
+            contents.push('\n');
+        }
+        std::fs::write(path, contents).map_err(|e| Error::IoError(path.into(), e))?;
+        Ok(rows.len())
+    }
+
+    /// `value` as a field: the extracted term for an e-class, and otherwise
+    /// a literal that [`EGraph::parse_field`] reads back.
+    fn table_field(&mut self, value: Value) -> Result<String, Error> {
+        let sort = self.get_sort(&value).unwrap().clone();
+        if sort.is_eq_sort() {
+            return Ok(self.extract(value)?.1.to_string());
+        }
+        Ok(match sort.make_expr(value) {
+            Expr::Lit(Literal::String(s)) => s.to_string(),
+            Expr::Lit(lit) => lit.to_string(),
+            Expr::Call(op, args) if op == "rational".into() => ListDisplay(args, "/").to_string(),
+            expr => expr.to_string(),
+        })
+    }
+}
+
+/// The fields of a record, along with the line it starts on.
+type Record = (usize, Vec<String>);
+
+/// Split `contents` into records of fields separated by `delimiter`.
+///
+/// A field in double quotes may contain delimiters and newlines, with `""`
+/// standing for a quote. This holds for tab-separated files too, since
+/// `output-table` quotes fields that would not read back otherwise. Other
+/// fields are trimmed, and blank lines are skipped.
+fn read_records(contents: &str, delimiter: char) -> Result<Vec<Record>, (usize, String)> {
+    let is_space = |c: &char| *c != delimiter && *c != '\n' && c.is_whitespace();
+    let mut records = vec![];
+    let mut chars = contents.chars().peekable();
+    let mut line = 1;
+    while chars.peek().is_some() {
+        let start = line;
+        let mut fields = vec![];
+        let mut quoted = false;
+        loop {
+            let mut field = String::new();
+            while chars.next_if(is_space).is_some() {}
+            if chars.next_if_eq(&'"').is_some() {
+                quoted = true;
+                loop {
+                    match chars.next() {
+                        None => return Err((start, "unterminated quoted field".into())),
+                        Some('"') if chars.next_if_eq(&'"').is_none() => break,
+                        Some(c) => {
+                            line += (c == '\n') as usize;
+                            field.push(c);
+                        }
+                    }
+                }
+                while chars.next_if(is_space).is_some() {}
+                if chars.peek().map_or(false, |&c| c != delimiter && c != '\n') {
+                    return Err((line, "unexpected text after quoted field".into()));
+                }
+            } else {
+                while let Some(c) = chars.next_if(|&c| c != delimiter && c != '\n') {
+                    field.push(c);
+                }
+                field.truncate(field.trim_end().len());
+            }
+            fields.push(field);
+
+            match chars.next() {
+                Some('\n') => {
+                    line += 1;
+                    break;
+                }
+                None => break,
+                Some(_) => {}
+            }
+        }
+        if quoted || fields.len() > 1 || !fields[0].is_empty() {
+            records.push((start, fields));
+        }
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_records() {
+        let contents = "a, \"b,\"\"c\"\"\" \r\n\n\"multi\nline\",d\n";
+        assert_eq!(
+            read_records(contents, ','),
+            Ok(vec![
+                (1, vec!["a".into(), "b,\"c\"".into()]),
+                (3, vec!["multi\nline".into(), "d".into()]),
+            ])
+        );
+        assert_eq!(
+            read_records("a,b\t c\n", '\t'),
+            Ok(vec![(1, vec!["a,b".into(), "c".into()])])
+        );
+        assert_eq!(
+            read_records("\"tab\there\"\t\" x \"\t\"say \"\"hi\"\"\"\n", '\t'),
+            Ok(vec![(
+                1,
+                vec!["tab\there".into(), " x ".into(), "say \"hi\"".into()]
+            )])
+        );
+        assert_eq!(
+            read_records("x\n\"y", ','),
+            Err((2, "unterminated quoted field".into()))
+        );
+    }
+
+    #[test]
+    fn test_table_fields() {
+        let mut egraph = EGraph::default();
+        egraph
+            .parse_and_run_program(
+                r#"(datatype Math (Num i64) (Var String) (Add Math Math))
+                   (let sum (Add (Num 1) (Num 2)))
+                   (union sum (Num 3))
+                   (let x (Var "x y"))
+                   (let ratio (rational 3 4))
+                   (let name "say \"hi\"")"#,
+            )
+            .unwrap();
+        let mut field = |name: &str| {
+            let (_, value) = egraph
+                .eval_expr(&Expr::Var(name.into()), None, false)
+                .unwrap();
+            egraph.table_field(value).unwrap()
+        };
+        assert_eq!(field("sum"), "(Num 3)");
+        assert_eq!(field("x"), r#"(Var "x y")"#);
+        assert_eq!(field("ratio"), "3/4");
+        assert_eq!(field("name"), r#"say "hi""#);
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::extract::own_cost;
+use crate::*;
+
+/// The e-graph as written by [`EGraph::to_json`].
+///
+/// E-classes are numbered by their canonical ids, and e-nodes by their
+/// position in `nodes`, which lists functions in name order and each
+/// function's rows in insertion order.
+#[derive(Serialize)]
+struct JsonEGraph {
+    sorts: Vec<JsonSort>,
+    classes: Vec<JsonClass>,
+    nodes: Vec<JsonNode>,
+}
+
+#[derive(Serialize)]
+struct JsonSort {
+    name: String,
+    eq_sort: bool,
+}
+
+#[derive(Serialize)]
+struct JsonClass {
+    id: usize,
+    sort: String,
+    /// Indices into `nodes` of the e-nodes in this class.
+    nodes: Vec<usize>,
+}
+
+#[derive(Serialize)]
+struct JsonNode {
+    function: String,
+    children: Vec<JsonChild>,
+    /// The class of the e-node for functions returning an eq-sort, and its
+    /// value otherwise.
+    output: JsonChild,
+    /// The cost of the e-node alone, not counting its children's classes.
+    cost: Cost,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum JsonChild {
+    Class { class: usize },
+    Leaf { sort: String, value: String },
+}
+
+impl EGraph {
+    /// Dump the e-classes and e-nodes of the e-graph as JSON, for external
+    /// visualizers. Global variables introduced by `define` are left out.
+    pub fn to_json(&mut self) -> Result<String, Error> {
+        self.rebuild()?;
+
+        let mut names: Vec<Symbol> = (self.functions.iter())
+            .filter(|(_, function)| !function.is_variable)
+            .map(|(&name, _)| name)
+            .collect();
+        names.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+
+        let mut classes: BTreeMap<usize, JsonClass> = BTreeMap::new();
+        let mut nodes = vec![];
+        for name in names {
+            let function = &self.functions[&name];
+            for (inputs, output) in function.nodes.iter() {
+                let output = self.json_value(output.value);
+                if let JsonChild::Class { class } = output {
+                    classes
+                        .entry(class)
+                        .or_insert_with(|| JsonClass {
+                            id: class,
+                            sort: function.schema.output.name().to_string(),
+                            nodes: vec![],
+                        })
+                        .nodes
+                        .push(nodes.len());
+                }
+                nodes.push(JsonNode {
+                    function: name.to_string(),
+                    children: inputs.iter().map(|&v| self.json_value(v)).collect(),
+                    output,
+                    cost: own_cost(&*self.cost_model, function, inputs),
+                });
+            }
+        }
+
+        let mut sorts: Vec<JsonSort> = (self.proof_state.type_info.sorts.values())
+            .map(|sort| JsonSort {
+                name: sort.name().to_string(),
+                eq_sort: sort.is_eq_sort(),
+            })
+            .collect();
+        sorts.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let json = JsonEGraph {
+            sorts,
+            classes: classes.into_values().collect(),
+            nodes,
+        };
+        Ok(serde_json::to_string_pretty(&json).expect("e-graphs can always be serialized"))
+    }
+
+    fn json_value(&self, value: Value) -> JsonChild {
+        let sort = self.get_sort(&value).unwrap();
+        if sort.is_eq_sort() {
+            JsonChild::Class {
+                class: usize::from(self.find(Id::from(value.bits as usize))),
+            }
+        } else {
+            JsonChild::Leaf {
+                sort: sort.name().to_string(),
+                value: sort.make_expr(value).to_string(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        let mut egraph = EGraph::default();
+        egraph
+            .parse_and_run_program(
+                "(datatype Math (Num i64) (Add Math Math :cost 3))
+                 (union (Add (Num 1) (Num 2)) (Add (Num 2) (Num 1)))",
+            )
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_str(&egraph.to_json().unwrap()).unwrap();
+
+        let nodes = json["nodes"].as_array().unwrap();
+        assert_eq!(nodes.len(), 4);
+        assert_eq!(nodes[0]["function"], "Add");
+        assert_eq!(nodes[0]["output"], nodes[1]["output"]);
+        assert_eq!(nodes[0]["cost"], 3);
+        assert_eq!(nodes[2]["function"], "Num");
+        assert_eq!(nodes[2]["children"][0]["value"], "1");
+        assert_eq!(nodes[2]["cost"], 2);
+
+        let classes = json["classes"].as_array().unwrap();
+        assert_eq!(classes.len(), 3);
+        assert!(json["sorts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|sort| sort["name"] == "Math" && sort["eq_sort"].as_bool() == Some(true)));
+    }
+}
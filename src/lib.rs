@@ -1,8 +1,16 @@
 pub mod ast;
+mod dot;
+mod explain;
 mod extract;
 mod function;
 mod gj;
+mod input;
+#[cfg(feature = "serde-1")]
+mod json;
+mod profile;
 mod proofs;
+#[cfg(feature = "serde-1")]
+mod serialize;
 pub mod sort;
 mod typecheck;
 mod typechecking;
@@ -21,12 +29,11 @@ use proofs::ProofState;
 use symbolic_expressions::Sexp;
 
 use ast::*;
-use typechecking::{TypeInfo, UNIT_SYM};
+use typechecking::UNIT_SYM;
 
 use std::fmt::{Formatter, Write};
 use std::fs::File;
 use std::hash::Hash;
-use std::io::Read;
 use std::iter::once;
 use std::mem;
 use std::ops::{Deref, Range};
@@ -37,6 +44,12 @@ use typecheck::Program;
 
 type ArcSort = Arc<dyn Sort>;
 
+pub use explain::Explanation;
+use extract::ExtractionCache;
+pub use extract::{Bindings, Cost, CostModel, DefaultCostModel};
+pub use profile::{IterationProfile, Profile, RuleProfile, RulesetProfile};
+pub use typechecking::{FuncType, TypeInfo};
+pub use unionfind::Justification;
 pub use value::*;
 
 use function::*;
@@ -66,7 +79,16 @@ pub struct RunReport {
 pub struct ExtractReport {
     pub cost: usize,
     pub expr: Expr,
-    pub variants: Vec<Expr>,
+    /// Let-bindings for subterms shared by `expr`, in dependency order.
+    /// Only DAG extraction produces these.
+    pub bindings: Bindings,
+    /// Whether `cost` is known to be minimal for the extraction mode. Tree
+    /// extraction proves this only under a monotone cost model (see
+    /// [`CostModel::is_monotone`]), greedy DAG extraction never does, and
+    /// exact extraction only does when it finishes within its time limit.
+    pub optimal: bool,
+    /// The cheapest distinct terms and their tree costs, cheapest first.
+    pub variants: Vec<(Cost, Expr)>,
 }
 
 impl RunReport {
@@ -151,8 +173,12 @@ impl PrimitiveLike for SimplePrimitive {
 
 #[derive(Clone)]
 pub struct EGraph {
-    egraphs: Vec<Self>,
+    egraphs: Vec<Checkpoint>,
     unionfind: UnionFind,
+    /// The commands run so far that declare sorts, functions, rules and
+    /// globals, which is what a snapshot needs to replay.
+    #[cfg(feature = "serde-1")]
+    declarations: Vec<NCommand>,
     pub(crate) proof_state: ProofState,
     functions: HashMap<Symbol, Function>,
     rulesets: HashMap<Symbol, HashMap<Symbol, Rule>>,
@@ -161,10 +187,29 @@ pub struct EGraph {
     pub test_proofs: bool,
     pub match_limit: usize,
     pub node_limit: usize,
+    /// Once this passes, running rules stops and the next command fails with
+    /// [`Error::TimeLimit`].
+    pub deadline: Option<Instant>,
     pub fact_directory: Option<PathBuf>,
     pub seminaive: bool,
     extract_report: Option<ExtractReport>,
     run_report: Option<RunReport>,
+    iterations: Vec<IterationProfile>,
+    cost_model: Rc<dyn CostModel>,
+    extraction_cache: ExtractionCache,
+}
+
+/// The state saved by [`EGraph::push`]. Tables, the union-find and the
+/// interners of container sorts keep undo logs of their own, and the
+/// declarations and local types only grow, so they are truncated on pop.
+/// This only holds the rest of the e-graph, whose size depends on the number
+/// of sorts, functions, rules and globals declared.
+#[derive(Clone)]
+struct Checkpoint {
+    egraph: Box<EGraph>,
+    interned: Vec<(ArcSort, usize)>,
+    #[cfg(feature = "serde-1")]
+    declarations: usize,
 }
 
 #[derive(Clone, Debug)]
@@ -184,11 +229,14 @@ impl Default for EGraph {
         let mut egraph = Self {
             egraphs: vec![],
             unionfind: Default::default(),
+            #[cfg(feature = "serde-1")]
+            declarations: vec![],
             functions: Default::default(),
             rulesets: Default::default(),
             proof_state: ProofState::default(),
             match_limit: usize::MAX,
             node_limit: usize::MAX,
+            deadline: None,
             timestamp: 0,
             proofs_enabled: false,
             test_proofs: false,
@@ -196,6 +244,9 @@ impl Default for EGraph {
             seminaive: true,
             extract_report: None,
             run_report: None,
+            iterations: vec![],
+            cost_model: Rc::new(DefaultCostModel),
+            extraction_cache: Default::default(),
         };
         egraph.rulesets.insert("".into(), Default::default());
         egraph
@@ -207,20 +258,117 @@ impl Default for EGraph {
 pub struct NotFoundError(Expr);
 
 impl EGraph {
+    /// Save the state of the e-graph, to be restored by [`EGraph::pop`].
+    ///
+    /// The tables and the union-find are not copied, nor is anything that
+    /// grows with every command run, so this takes time proportional to the
+    /// number of declarations rather than to the number of e-nodes or
+    /// commands. The matching `pop` takes time proportional to the changes
+    /// made since.
     pub fn push(&mut self) {
-        self.egraphs.push(self.clone());
+        for function in self.functions.values_mut() {
+            function.checkpoint();
+        }
+        self.unionfind.checkpoint();
+        let interned = self
+            .proof_state
+            .type_info
+            .sorts
+            .values()
+            .map(|sort| (sort.clone(), sort.num_interned()))
+            .collect();
+
+        let functions = mem::take(&mut self.functions);
+        let unionfind = mem::take(&mut self.unionfind);
+        let egraphs = mem::take(&mut self.egraphs);
+        let extraction_cache = mem::take(&mut self.extraction_cache);
+        let iterations = mem::take(&mut self.iterations);
+        let local_types = mem::take(&mut self.proof_state.type_info.local_types);
+        #[cfg(feature = "serde-1")]
+        let declarations = mem::take(&mut self.declarations);
+        let egraph = Box::new(self.clone());
+        self.functions = functions;
+        self.unionfind = unionfind;
+        self.egraphs = egraphs;
+        self.extraction_cache = extraction_cache;
+        self.iterations = iterations;
+        self.proof_state.type_info.local_types = local_types;
+        #[cfg(feature = "serde-1")]
+        {
+            self.declarations = declarations;
+        }
+
+        self.egraphs.push(Checkpoint {
+            egraph,
+            interned,
+            #[cfg(feature = "serde-1")]
+            declarations: self.declarations.len(),
+        });
     }
 
     pub fn pop(&mut self) -> Result<(), Error> {
         match self.egraphs.pop() {
-            Some(e) => {
-                *self = e;
+            Some(Checkpoint {
+                egraph,
+                interned,
+                #[cfg(feature = "serde-1")]
+                declarations,
+            }) => {
+                let mut functions = mem::take(&mut self.functions);
+                // drop the functions declared since the push
+                functions.retain(|_, function| function.rollback());
+                let mut unionfind = mem::take(&mut self.unionfind);
+                unionfind.rollback();
+                for (sort, len) in interned {
+                    sort.truncate_interned(len);
+                }
+
+                let egraphs = mem::take(&mut self.egraphs);
+                let iterations = mem::take(&mut self.iterations);
+                let rulesets = mem::take(&mut self.rulesets);
+                let mut local_types = mem::take(&mut self.proof_state.type_info.local_types);
+                #[cfg(feature = "serde-1")]
+                let mut popped_declarations = mem::take(&mut self.declarations);
+                *self = *egraph;
+                self.functions = functions;
+                self.unionfind = unionfind;
+                self.egraphs = egraphs;
+                // command ids handed out since the push are handed out again
+                let next_id = self.proof_state.desugar.next_command_id;
+                local_types.retain(|id, _| *id < next_id);
+                self.proof_state.type_info.local_types = local_types;
+                #[cfg(feature = "serde-1")]
+                {
+                    popped_declarations.truncate(declarations);
+                    self.declarations = popped_declarations;
+                }
+                // what ran since the push still counts towards the profile
+                self.iterations = iterations;
+                for (name, rules) in self.rulesets.iter_mut() {
+                    for (rule_name, rule) in rules.iter_mut() {
+                        if let Some(popped) = rulesets.get(name).and_then(|r| r.get(rule_name)) {
+                            rule.matches = popped.matches;
+                            rule.search_time = popped.search_time;
+                            rule.apply_time = popped.apply_time;
+                        }
+                    }
+                }
                 Ok(())
             }
             None => Err(Error::Pop),
         }
     }
 
+    /// Drop the state saved by the last [`EGraph::push`], keeping the
+    /// changes made since.
+    fn commit(&mut self) {
+        self.egraphs.pop().expect("no push to commit");
+        for function in self.functions.values_mut() {
+            function.commit();
+        }
+        self.unionfind.commit();
+    }
+
     pub fn union(&mut self, id1: Id, id2: Id, sort: Symbol) -> Id {
         self.unionfind.union(id1, id2, sort)
     }
@@ -306,6 +454,7 @@ impl EGraph {
                 break;
             }
         }
+        self.unionfind.set_justification(|| Justification::Action);
         self.debug_assert_invariants();
         Ok(updates)
     }
@@ -314,6 +463,8 @@ impl EGraph {
         let mut new_unions = 0;
         let mut deferred_merges = Vec::new();
         for function in self.functions.values_mut() {
+            self.unionfind
+                .set_justification(|| Justification::Congruence(function.decl.name));
             let (unions, merges) = function.rebuild(&mut self.unionfind, self.timestamp)?;
             if !merges.is_empty() {
                 deferred_merges.push((function.decl.name, merges));
@@ -330,6 +481,8 @@ impl EGraph {
         let mut stack = Vec::new();
         let mut function = self.functions.get_mut(&func).unwrap();
         let n_unions = self.unionfind.n_unions();
+        self.unionfind
+            .set_justification(|| Justification::Congruence(func));
         let merge_prog = match &function.merge.merge_vals {
             MergeFn::Expr(e) => Some(e.clone()),
             MergeFn::AssertEq | MergeFn::Union => None,
@@ -385,6 +538,7 @@ impl EGraph {
                 merge_action: vec![],
                 default: None,
                 cost: variant.cost,
+                unextractable: variant.unextractable,
             },
             false,
         )?;
@@ -422,7 +576,7 @@ impl EGraph {
             for (a, t) in ins.iter().copied().zip(&schema.input) {
                 s.push(' ');
                 let e = if t.is_eq_sort() {
-                    self.extract(a).1
+                    self.extract(a)?.1
                 } else {
                     t.make_expr(a)
                 };
@@ -433,7 +587,7 @@ impl EGraph {
                 s.push(')');
             } else {
                 let e = if schema.output.is_eq_sort() {
-                    self.extract(out.value).1
+                    self.extract(out.value)?.1
                 } else {
                     schema.output.make_expr(out.value)
                 };
@@ -524,13 +678,26 @@ impl EGraph {
             let updates = self.rebuild_nofail();
             log::debug!("database size: {}", self.num_tuples());
             log::debug!("Made {updates} updates (iteration {i})");
-            report.rebuild_time += rebuild_start.elapsed();
+            let rebuild_time = rebuild_start.elapsed();
+            report.rebuild_time += rebuild_time;
+            self.iterations.push(IterationProfile {
+                ruleset: ruleset.to_string(),
+                search_time: subreport.search_time.as_secs_f64(),
+                apply_time: subreport.apply_time.as_secs_f64(),
+                rebuild_time: rebuild_time.as_secs_f64(),
+                db_size: self.num_tuples(),
+            });
             self.timestamp += 1;
             if !subreport.updated {
                 log::info!("Breaking early at iteration {}!", i);
                 break;
             }
 
+            if self.timed_out() {
+                log::warn!("Time limit reached at iteration {}. Stopping!", i);
+                break;
+            }
+
             if self.num_tuples() > self.node_limit {
                 log::warn!(
                     "Node limit reached at iteration {}, {} nodes. Stopping!",
@@ -651,6 +818,10 @@ impl EGraph {
             } else {
                 for values in all_values.chunks(num_vars) {
                     rule.matches += 1;
+                    self.unionfind.set_justification(|| {
+                        let vars = rule.query.vars.keys().copied();
+                        Justification::Rule(*name, vars.zip(values.iter().copied()).collect())
+                    });
                     // we can ignore results here
                     stack.clear();
                     let _ = self.run_actions(stack, values, &rule.program, true);
@@ -659,6 +830,7 @@ impl EGraph {
 
             rule.apply_time += rule_apply_start.elapsed();
         }
+        self.unionfind.set_justification(|| Justification::Action);
         self.rulesets.insert(ruleset, rules);
         let apply_elapsed = apply_start.elapsed();
         report.apply_time += apply_elapsed;
@@ -748,6 +920,15 @@ impl EGraph {
             "enable_proofs" => {
                 panic!("enable_proofs must be set as the first line of the file");
             }
+            "enable_explanations" => {
+                if let Expr::Lit(Literal::Int(i)) = value {
+                    if i != 0 {
+                        self.enable_explanations();
+                    }
+                } else {
+                    panic!("enable_explanations must be an integer");
+                }
+            }
             "match_limit" => {
                 if let Expr::Lit(Literal::Int(i)) = value {
                     self.match_limit = i as usize;
@@ -801,7 +982,9 @@ impl EGraph {
                 pre_rebuild.elapsed().as_millis()
             );
         }
-        Ok(match command {
+        #[cfg(feature = "serde-1")]
+        let declaration = serialize::is_declaration(&command).then(|| command.clone());
+        let msg = match command {
             NCommand::SetOption { name, value } => {
                 let str = format!("Set option {} to {}", name, value);
                 self.set_option(name.into(), value);
@@ -833,15 +1016,29 @@ impl EGraph {
                     "Skipping schedule.".to_string()
                 }
             }
-            NCommand::Extract { var, variants } => {
+            NCommand::Extract {
+                var,
+                variants,
+                mode,
+            } => {
                 let expr = Expr::Var(var);
                 if should_run {
                     // TODO typecheck
-                    let report = self.extract_expr(expr, variants)?;
-                    let mut msg = format!("Extracted with cost {}: {}", report.cost, report.expr);
+                    let report = self.extract_expr(expr, mode, variants)?;
+                    let mut msg = format!(
+                        "Extracted with cost {}: {}",
+                        report.cost,
+                        extract::LetDisplay(&report.bindings, &report.expr)
+                    );
                     if variants > 0 {
                         let line = "\n    ";
-                        let v_exprs = ListDisplay(&report.variants, line);
+                        let v_exprs = ListDisplay(
+                            report
+                                .variants
+                                .iter()
+                                .map(|(cost, expr)| format!("{expr} (cost {cost})")),
+                            line,
+                        );
                         write!(msg, "\nVariants of {}:{line}{v_exprs}", report.expr).unwrap();
                     }
                     self.extract_report = Some(report);
@@ -916,58 +1113,16 @@ impl EGraph {
                 }
                 "Command failed as expected.".into()
             }
-            NCommand::Input { name, file } => {
-                let func = self.functions.get_mut(&name).unwrap();
-                let is_unit = func.schema.output.name().as_str() == "Unit";
-
+            NCommand::Input {
+                name,
+                file,
+                format,
+                header,
+            } => {
                 let mut filename = self.fact_directory.clone().unwrap_or_default();
                 filename.push(file.as_str());
-
-                // check that the function uses supported types
-                for t in &func.schema.input {
-                    match t.name().as_str() {
-                        "i64" | "String" => {}
-                        s => panic!("Unsupported type {} for input", s),
-                    }
-                }
-                match func.schema.output.name().as_str() {
-                    "i64" | "String" | "Unit" => {}
-                    s => panic!("Unsupported type {} for input", s),
-                }
-
-                log::info!("Opening file '{:?}'...", filename);
-                let mut f = File::open(filename).unwrap();
-                let mut contents = String::new();
-                f.read_to_string(&mut contents).unwrap();
-
-                let mut actions: Vec<Action> = vec![];
-                let mut str_buf: Vec<&str> = vec![];
-                for line in contents.lines() {
-                    str_buf.clear();
-                    str_buf.extend(line.split('\t').map(|s| s.trim()));
-                    if str_buf.is_empty() {
-                        continue;
-                    }
-
-                    let parse = |s: &str| -> Expr {
-                        if let Ok(i) = s.parse() {
-                            Expr::Lit(Literal::Int(i))
-                        } else {
-                            Expr::Lit(Literal::String(s.into()))
-                        }
-                    };
-
-                    let mut exprs: Vec<Expr> = str_buf.iter().map(|&s| parse(s)).collect();
-
-                    actions.push(if is_unit {
-                        Action::Expr(Expr::Call(name, exprs))
-                    } else {
-                        let out = exprs.pop().unwrap();
-                        Action::Set(name, exprs, out)
-                    });
-                }
-                self.eval_actions(&actions)?;
-                format!("Read {} facts into {name} from '{file}'.", actions.len())
+                let n_rows = self.input_file(name, &filename, format, header)?;
+                format!("Read {n_rows} facts into {name} from '{file}'.")
             }
             NCommand::Output { file, exprs } => {
                 let mut filename = self.fact_directory.clone().unwrap_or_default();
@@ -982,13 +1137,60 @@ impl EGraph {
 
                 for expr in exprs {
                     use std::io::Write;
-                    let res = self.extract_expr(expr, 1)?;
+                    let res = self.extract_expr(expr, ExtractMode::Tree, 0)?;
                     writeln!(f, "{}", res.expr).map_err(|e| Error::IoError(filename.clone(), e))?;
                 }
 
                 format!("Output to '{filename:?}'.")
             }
-        })
+            NCommand::OutputTable { name, file } => {
+                let mut filename = self.fact_directory.clone().unwrap_or_default();
+                filename.push(file.as_str());
+                let n_rows = self.output_table(name, &filename)?;
+                format!("Wrote {n_rows} rows of {name} to '{filename:?}'.")
+            }
+            #[cfg(feature = "serde-1")]
+            NCommand::Save(file) => {
+                let mut filename = self.fact_directory.clone().unwrap_or_default();
+                filename.push(file.as_str());
+                self.save(&filename)?;
+                format!("Saved to '{filename:?}'.")
+            }
+            #[cfg(feature = "serde-1")]
+            NCommand::Load(file) => {
+                let mut filename = self.fact_directory.clone().unwrap_or_default();
+                filename.push(file.as_str());
+                self.load(&filename)?;
+                format!("Loaded '{filename:?}'.")
+            }
+            #[cfg(feature = "serde-1")]
+            NCommand::ExportJson(file) => {
+                let mut filename = self.fact_directory.clone().unwrap_or_default();
+                filename.push(file.as_str());
+                let json = self.to_json()?;
+                std::fs::write(&filename, json).map_err(|e| Error::IoError(filename.clone(), e))?;
+                format!("Exported to '{filename:?}'.")
+            }
+            #[cfg(not(feature = "serde-1"))]
+            NCommand::Save(_) | NCommand::Load(_) | NCommand::ExportJson(_) => {
+                return Err(Error::FeatureDisabled(command.to_string(), "serde-1"));
+            }
+            NCommand::ExportDot {
+                file,
+                functions,
+                root,
+            } => {
+                let mut filename = self.fact_directory.clone().unwrap_or_default();
+                filename.push(file.as_str());
+                let root = root.map(Expr::Var);
+                let dot = self.to_dot(&functions, root.as_ref())?;
+                std::fs::write(&filename, dot).map_err(|e| Error::IoError(filename.clone(), e))?;
+                format!("Exported to '{filename:?}'.")
+            }
+        };
+        #[cfg(feature = "serde-1")]
+        self.declarations.extend(declaration);
+        Ok(msg)
     }
 
     pub fn clear(&mut self) {
@@ -1001,28 +1203,44 @@ impl EGraph {
         self.push();
         let (_t, value) = self.eval_expr(&expr, None, true).unwrap();
         self.run_report = Some(self.run_rules(config));
-        let (cost, expr) = self.extract(value);
+        let extracted = self.extract(value);
         self.pop().unwrap();
+        let (cost, expr) = extracted?;
         Ok(ExtractReport {
             cost,
             expr,
+            bindings: vec![],
+            optimal: self.cost_model.is_monotone(),
             variants: vec![],
         })
     }
     // Extract an expression from the current state, returning the cost, the extracted expression and some number
     // of other variants, if variants is not zero.
-    pub fn extract_expr(&mut self, e: Expr, variants: usize) -> Result<ExtractReport, Error> {
+    pub fn extract_expr(
+        &mut self,
+        e: Expr,
+        mode: ExtractMode,
+        variants: usize,
+    ) -> Result<ExtractReport, Error> {
         let (_t, value) = self.eval_expr(&e, None, true)?;
-        let (cost, expr) = self.extract(value);
-        let exprs = match variants {
-            0 => vec![],
-            1 => vec![expr.clone()],
-            _ => self.extract_variants(value, variants),
+        let (cost, bindings, expr, optimal) = match mode {
+            ExtractMode::Tree => {
+                let (cost, expr) = self.extract(value)?;
+                (cost, vec![], expr, self.cost_model.is_monotone())
+            }
+            ExtractMode::Dag => {
+                let (cost, bindings, expr) = self.extract_dag(value)?;
+                (cost, bindings, expr, false)
+            }
+            ExtractMode::Exact { time_limit } => self.extract_exact(value, time_limit)?,
         };
+        let variants = self.extract_variants(value, variants);
         Ok(ExtractReport {
             cost,
             expr,
-            variants: exprs,
+            bindings,
+            optimal,
+            variants,
         })
     }
 
@@ -1039,11 +1257,13 @@ impl EGraph {
                 merge: None,
                 merge_action: vec![],
                 cost: None,
+                unextractable: false,
             },
             true,
         )?;
         let f = self.functions.get_mut(&name).unwrap();
         let id = self.unionfind.make_set();
+        self.unionfind.set_origin(id, name, &[]);
         let value = Value::from_id(sort.name(), id);
         f.insert(&[], value, self.timestamp);
         Ok(())
@@ -1066,6 +1286,7 @@ impl EGraph {
                 merge: None,
                 merge_action: vec![],
                 cost,
+                unextractable: false,
             },
             true,
         )?;
@@ -1075,26 +1296,62 @@ impl EGraph {
     }
 
     // process the commands but don't run them
-    pub fn process_commands(
+    pub fn process_commands(&mut self, program: Vec<Command>) -> Result<Vec<NormCommand>, Error> {
+        self.process_spanned_commands(program.into_iter().map(|c| (None, c)).collect())
+    }
+
+    /// Parse and process `input` without running it.
+    pub fn parse_and_process_program(&mut self, input: &str) -> Result<Vec<NormCommand>, Error> {
+        self.parse_and_process_program_from(None, input)
+    }
+
+    /// Like [`EGraph::parse_and_process_program`], for `input` read from the
+    /// file `filename`, which errors name.
+    pub fn parse_and_process_program_with_filename(
         &mut self,
-        mut program: Vec<Command>,
+        filename: String,
+        input: &str,
+    ) -> Result<Vec<NormCommand>, Error> {
+        self.parse_and_process_program_from(Some(filename), input)
+    }
+
+    fn parse_and_process_program_from(
+        &mut self,
+        filename: Option<String>,
+        input: &str,
+    ) -> Result<Vec<NormCommand>, Error> {
+        let parsed = self.proof_state.parse_program(filename, input)?;
+        self.process_spanned_commands(
+            parsed
+                .into_iter()
+                .map(|(span, c)| (Some(span), c))
+                .collect(),
+        )
+    }
+
+    fn process_spanned_commands(
+        &mut self,
+        mut program: Vec<(Option<Span>, Command)>,
     ) -> Result<Vec<NormCommand>, Error> {
         let mut result = vec![];
-        if let Some(Command::SetOption {
-            name,
-            value: Expr::Lit(Literal::Int(1)),
-        }) = program.first()
+        if let Some((
+            _,
+            Command::SetOption {
+                name,
+                value: Expr::Lit(Literal::Int(1)),
+            },
+        )) = program.first()
         {
             if name == &"enable_proofs".into() {
                 program = program.split_off(1);
                 for step in self.proof_state.proof_header() {
-                    result.extend(self.process_command(step)?);
+                    result.extend(self.process_command(step, None)?);
                 }
                 self.proofs_enabled = true;
             }
         }
 
-        for command in program {
+        for (span, command) in program {
             match command {
                 Command::Push(num) => {
                     for _ in 0..num {
@@ -1109,7 +1366,7 @@ impl EGraph {
                 }
                 _ => {}
             }
-            result.extend(self.process_command(command)?);
+            result.extend(self.process_command(command, span.as_ref())?);
         }
         Ok(result)
     }
@@ -1118,27 +1375,46 @@ impl EGraph {
         self.proof_state.desugar.number_underscores = underscores;
     }
 
-    fn process_command(&mut self, command: Command) -> Result<Vec<NormCommand>, Error> {
-        let program_desugared = self.proof_state.desugar.desugar_program(
-            vec![command],
-            self.test_proofs,
-            self.seminaive,
-        )?;
+    /// Look for included files that are not next to the file including them
+    /// in each of `dirs`, in order.
+    pub fn set_include_path(&mut self, dirs: Vec<PathBuf>) {
+        self.proof_state.desugar.include_path = dirs;
+    }
 
-        let type_info_before = self.proof_state.type_info.clone();
+    /// Desugar and typecheck `command`, which came from the source at `span`
+    /// if it was parsed.
+    fn process_command(
+        &mut self,
+        command: Command,
+        span: Option<&Span>,
+    ) -> Result<Vec<NormCommand>, Error> {
+        let desugar = &mut self.proof_state.desugar;
+        let program_desugared = desugar
+            .resolve_names(command)
+            .and_then(|command| {
+                desugar.desugar_program(vec![command], span, self.test_proofs, self.seminaive)
+            })
+            .map_err(|e| e.with_span(span))?;
+
+        // cloning the type info takes time proportional to the commands run
+        // so far, so only do it when it is needed
+        let type_info_before = self
+            .proofs_enabled
+            .then(|| self.proof_state.type_info.clone());
         self.proof_state
             .type_info
             .typecheck_program(&program_desugared)?;
 
-        let program = if self.proofs_enabled {
+        let program = if let Some(type_info_before) = type_info_before {
             // proofs require type info, so
             // we need to pass in the desugar
             let proofs = self.proof_state.add_proofs(program_desugared);
 
-            let final_desugared =
-                self.proof_state
-                    .desugar
-                    .desugar_program(proofs, false, self.seminaive)?;
+            let final_desugared = self
+                .proof_state
+                .desugar
+                .desugar_program(proofs, span, false, self.seminaive)
+                .map_err(|e| e.with_span(span))?;
 
             // revert back to the type info before
             // proofs were added, typecheck again
@@ -1164,14 +1440,25 @@ impl EGraph {
         }
     }
 
-    pub fn run_program(&mut self, mut program: Vec<Command>) -> Result<Vec<String>, Error> {
+    pub fn run_program(&mut self, program: Vec<Command>) -> Result<Vec<String>, Error> {
+        self.run_spanned_program(program.into_iter().map(|c| (None, c)).collect())
+    }
+
+    /// Run `program`, attributing errors to the source each command came from.
+    fn run_spanned_program(
+        &mut self,
+        mut program: Vec<(Option<Span>, Command)>,
+    ) -> Result<Vec<String>, Error> {
         let mut msgs = vec![];
         let should_run = true;
 
-        if let Some(Command::SetOption {
-            name,
-            value: Expr::Lit(Literal::Int(1)),
-        }) = program.first()
+        if let Some((
+            _,
+            Command::SetOption {
+                name,
+                value: Expr::Lit(Literal::Int(1)),
+            },
+        )) = program.first()
         {
             if name == &"enable_proofs".into() {
                 self.enable_proofs();
@@ -1179,13 +1466,18 @@ impl EGraph {
             }
         }
 
-        for command in program {
+        for (span, command) in program {
             // Important to process each command individually
             // because push and pop create new scopes
-            for processed in self.process_command(command)? {
-                let msg = self.run_command(processed.command, should_run)?;
+            for processed in self.process_command(command, span.as_ref())? {
+                let msg = self
+                    .run_command(processed.command, should_run)
+                    .map_err(|e| e.with_span(processed.metadata.span.as_ref()))?;
                 log::info!("{}", msg);
                 msgs.push(msg);
+                if self.timed_out() {
+                    return Err(Error::TimeLimit);
+                }
             }
         }
 
@@ -1203,18 +1495,72 @@ impl EGraph {
     }
 
     pub fn parse_program(&self, input: &str) -> Result<Vec<Command>, Error> {
-        self.proof_state.parse_program(input)
+        self.parse_program_from(None, input)
+    }
+
+    /// Like [`EGraph::parse_program`], for `input` read from the file
+    /// `filename`, which parse errors name.
+    pub fn parse_program_with_filename(
+        &self,
+        filename: String,
+        input: &str,
+    ) -> Result<Vec<Command>, Error> {
+        self.parse_program_from(Some(filename), input)
     }
 
+    fn parse_program_from(
+        &self,
+        filename: Option<String>,
+        input: &str,
+    ) -> Result<Vec<Command>, Error> {
+        let parsed = self.proof_state.parse_program(filename, input)?;
+        Ok(parsed.into_iter().map(|(_, command)| command).collect())
+    }
+
+    /// Parse and run `input`. Errors report where in `input` the command that
+    /// caused them is.
     pub fn parse_and_run_program(&mut self, input: &str) -> Result<Vec<String>, Error> {
-        let parsed = self.proof_state.parse_program(input)?;
-        self.run_program(parsed)
+        self.parse_and_run_program_from(None, input)
+    }
+
+    /// Like [`EGraph::parse_and_run_program`], for `input` read from the file
+    /// `filename`, which errors name.
+    pub fn parse_and_run_program_with_filename(
+        &mut self,
+        filename: String,
+        input: &str,
+    ) -> Result<Vec<String>, Error> {
+        self.parse_and_run_program_from(Some(filename), input)
+    }
+
+    fn parse_and_run_program_from(
+        &mut self,
+        filename: Option<String>,
+        input: &str,
+    ) -> Result<Vec<String>, Error> {
+        let parsed = self.proof_state.parse_program(filename, input)?;
+        self.run_spanned_program(
+            parsed
+                .into_iter()
+                .map(|(span, c)| (Some(span), c))
+                .collect(),
+        )
+    }
+
+    fn timed_out(&self) -> bool {
+        self.deadline
+            .map_or(false, |deadline| Instant::now() >= deadline)
     }
 
     pub fn num_tuples(&self) -> usize {
         self.functions.values().map(|f| f.nodes.len()).sum()
     }
 
+    /// The sorts, functions, primitives and globals declared so far.
+    pub fn type_info(&self) -> &TypeInfo {
+        &self.proof_state.type_info
+    }
+
     pub(crate) fn get_sort(&self, value: &Value) -> Option<&ArcSort> {
         self.proof_state.type_info.sorts.get(&value.tag)
     }
@@ -1232,7 +1578,7 @@ impl EGraph {
 
 #[derive(Debug, Error)]
 pub enum Error {
-    #[error(transparent)]
+    #[error("{}", parse_error_message(.0))]
     ParseError(#[from] lalrpop_util::ParseError<usize, String, String>),
     #[error(transparent)]
     NotFoundError(#[from] NotFoundError),
@@ -1248,10 +1594,73 @@ pub enum Error {
     MergeError(Symbol, Value, Value),
     #[error("Tried to pop too much")]
     Pop,
+    #[error("Time limit exceeded")]
+    TimeLimit,
     #[error("Command should have failed.")]
     ExpectFail,
     #[error("IO error: {0}: {1}")]
     IoError(PathBuf, std::io::Error),
+    #[error("Cyclic include of {0}")]
+    IncludeCycle(PathBuf),
+    #[error("{0} is not exported by module {1}")]
+    NotExported(Symbol, Symbol),
+    #[error("Argument {1} of macro {0} is not a name")]
+    MacroArgument(Symbol, Expr),
+    #[error("Recursive expansion of macro {0}")]
+    MacroCycle(Symbol),
+    #[error(
+        "No term can be extracted for e-class {0}{}",
+        .1.map(|f| format!(" (introduced by {f})")).unwrap_or_default()
+    )]
+    ExtractionError(Id, Option<Symbol>),
+    #[error("The best term for e-class {0} contains itself: the cost model makes a term cheaper than its children")]
+    ExtractionCycle(Id),
+    #[error("No explanation for {0} = {1}")]
+    ExplainError(Expr, Expr),
+    #[error("{}:{1}: {2}", .0.display())]
+    InputError(PathBuf, usize, String),
+    #[error("{0} needs the {1} feature, which this build does not have")]
+    FeatureDisabled(String, &'static str),
+    #[error("Invalid snapshot: {0}")]
+    SnapshotError(String),
+    #[error("Cannot load {} before popping every push", .0.display())]
+    LoadInsidePush(PathBuf),
+    #[error("{0}: {1}{}", .0.quote())]
+    Spanned(Span, Box<Error>),
+}
+
+impl Error {
+    /// Attribute the error to the source at `span`, unless it already is.
+    pub(crate) fn with_span(self, span: Option<&Span>) -> Self {
+        match (self, span) {
+            (Error::Spanned(span, err), _) => Error::Spanned(span, err),
+            (err, Some(span)) => Error::Spanned(span.clone(), Box::new(err)),
+            (err, None) => err,
+        }
+    }
+}
+
+/// The message of a parse error, without the location that
+/// [`Error::Spanned`] reports.
+fn parse_error_message(err: &lalrpop_util::ParseError<usize, String, String>) -> String {
+    use lalrpop_util::ParseError::*;
+    let one_of = |expected: &Vec<String>| match expected.as_slice() {
+        [] => String::new(),
+        [token] => format!(", expected {token}"),
+        _ => format!(", expected one of {}", expected.join(", ")),
+    };
+    match err {
+        InvalidToken { .. } => "Invalid token".into(),
+        UnrecognizedEOF { expected, .. } => format!("Unexpected end of input{}", one_of(expected)),
+        UnrecognizedToken {
+            token: (_, token, _),
+            expected,
+        } => format!("Unexpected token `{token}`{}", one_of(expected)),
+        ExtraToken {
+            token: (_, token, _),
+        } => format!("Extra token `{token}`"),
+        User { error } => error.clone(),
+    }
 }
 
 fn safe_shl(a: usize, b: usize) -> usize {
@@ -1,70 +1,275 @@
 use clap::Parser;
-use egg_smol::EGraph;
-use std::io::{self, Read};
-use std::path::PathBuf;
+use egg_smol::ast::{Command, Expr, Literal};
+use egg_smol::{EGraph, Error, Profile};
+use instant::{Duration, Instant};
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+use serde::Serialize;
+use std::mem;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Parser)]
 struct Args {
     #[clap(short = 'F', long)]
     fact_directory: Option<PathBuf>,
+    /// Look for included files in this directory when they are not next to
+    /// the file including them
+    #[clap(short = 'I', long = "include")]
+    include_path: Vec<PathBuf>,
     #[clap(long)]
     naive: bool,
+    /// Stop running rules once the e-graph has this many tuples
+    #[clap(long)]
+    node_limit: Option<usize>,
+    /// Ban a rule once it has this many matches in an iteration
+    #[clap(long)]
+    match_limit: Option<usize>,
+    /// Run with proofs enabled
+    #[clap(long)]
+    proofs: bool,
+    /// Print the desugared commands instead of running them
+    #[clap(long)]
+    desugar: bool,
+    /// Stop running rules and exit with an error once this many seconds have
+    /// passed
+    #[clap(long)]
+    time_limit: Option<f64>,
+    /// Only log warnings and errors
+    #[clap(short, long)]
+    quiet: bool,
+    /// Write the time spent running each rule and iteration to this file as
+    /// JSON, separately for each input file
+    #[clap(long)]
+    profile: Option<PathBuf>,
+    /// Run all inputs in order against a single e-graph
+    #[clap(long)]
+    shared: bool,
     inputs: Vec<PathBuf>,
 }
 
+/// The profile of running one input file.
+#[derive(Serialize)]
+struct FileProfile<'a> {
+    file: &'a Path,
+    #[serde(flatten)]
+    profile: Profile,
+}
+
 fn main() {
+    let args = Args::parse();
+
+    // the REPL prints results itself, so logging them would repeat them
+    let quiet = args.quiet || args.inputs.is_empty();
     env_logger::Builder::new()
-        .filter_level(log::LevelFilter::Info)
+        .filter_level(if quiet {
+            log::LevelFilter::Warn
+        } else {
+            log::LevelFilter::Info
+        })
         .format_timestamp(None)
         .format_target(false)
         .parse_default_env()
         .init();
 
-    let args = Args::parse();
+    let deadline = (args.time_limit).map(|secs| Instant::now() + Duration::from_secs_f64(secs));
+
+    let enable_proofs = || Command::SetOption {
+        name: "enable_proofs".into(),
+        value: Expr::Lit(Literal::Int(1)),
+    };
 
     let mk_egraph = || {
         let mut egraph = EGraph::default();
         egraph.fact_directory = args.fact_directory.clone();
         egraph.seminaive = !args.naive;
+        egraph.node_limit = args.node_limit.unwrap_or(usize::MAX);
+        egraph.match_limit = args.match_limit.unwrap_or(usize::MAX);
+        egraph.deadline = deadline;
+        egraph.set_include_path(args.include_path.clone());
+        if args.proofs {
+            egraph.run_program(vec![enable_proofs()]).unwrap();
+        }
         egraph
     };
 
     if args.inputs.is_empty() {
-        let stdin = io::stdin();
-        log::info!("Welcome to Egglog!");
-        let mut egraph = mk_egraph();
-        let mut program = String::new();
-        stdin
-            .lock()
-            .read_to_string(&mut program)
-            .unwrap_or_else(|_| panic!("Failed to read program from stdin"));
-        match egraph.parse_and_run_program(&program) {
-            Ok(_msgs) => {}
-            Err(err) => {
-                log::error!("{}", err);
-            }
-        }
-
-        std::process::exit(1)
+        repl(mk_egraph(), args.desugar);
+        return;
     }
 
+    // with --shared, the e-graph that the next file runs against
+    let mut shared = None;
+    let mut profiles = vec![];
     for (idx, input) in args.inputs.iter().enumerate() {
         let s = std::fs::read_to_string(input).unwrap_or_else(|_| {
             let arg = input.to_string_lossy();
             panic!("Failed to read file {arg}")
         });
-        let mut egraph = mk_egraph();
-        match egraph.parse_and_run_program(&s) {
-            Ok(_msgs) => {}
+        let filename = input.to_string_lossy().into_owned();
+        if args.desugar {
+            let first = shared.is_none();
+            let mut egraph = shared.take().unwrap_or_else(|| {
+                let mut egraph = EGraph::default();
+                egraph.seminaive = !args.naive;
+                egraph.set_include_path(args.include_path.clone());
+                egraph
+            });
+            let mut commands = vec![];
+            if args.proofs && first {
+                commands = egraph
+                    .process_commands(vec![enable_proofs()])
+                    .unwrap_or_else(|err| fail(input, err));
+            }
+            commands.extend(
+                egraph
+                    .parse_and_process_program_with_filename(filename, &s)
+                    .unwrap_or_else(|err| fail(input, err)),
+            );
+            commands.iter().for_each(|command| println!("{command}"));
+            if args.shared {
+                shared = Some(egraph);
+            }
+            continue;
+        }
+
+        let mut egraph = shared.take().unwrap_or_else(mk_egraph);
+        let result = egraph.parse_and_run_program_with_filename(filename, &s);
+        if let Some(path) = &args.profile {
+            profiles.push(FileProfile {
+                file: input,
+                profile: egraph.profile(),
+            });
+            // with --shared, the next file starts a profile of its own
+            egraph.clear_profile();
+            let json = serde_json::to_string_pretty(&profiles).unwrap();
+            std::fs::write(path, json).unwrap_or_else(|_| {
+                let arg = path.to_string_lossy();
+                panic!("Failed to write profile {arg}")
+            });
+        }
+        if let Err(err) = result {
+            fail(input, err)
+        }
+
+        if args.shared {
+            shared = Some(egraph);
+        } else if idx == args.inputs.len() - 1 {
+            // no need to drop the egraph if we are going to exit
+            std::mem::forget(egraph)
+        }
+    }
+    std::mem::forget(shared)
+}
+
+/// Report an error in the file `input` and exit, with status 2 if the time
+/// limit ran out and 1 otherwise.
+fn fail<T>(input: &Path, err: Error) -> T {
+    match err {
+        // the error already says where in the file it is
+        Error::Spanned(..) => log::error!("{}", err),
+        _ => log::error!("{}: {}", input.display(), err),
+    }
+    exit(&err)
+}
+
+fn exit<T>(err: &Error) -> T {
+    std::process::exit(if matches!(err, Error::TimeLimit) {
+        2
+    } else {
+        1
+    })
+}
+
+/// Run each command as soon as its closing parenthesis is entered, printing
+/// its results (or the desugared commands, with `desugar`) and reporting
+/// errors without leaving the REPL. The history is kept in
+/// `~/.egglog_history` across sessions.
+fn repl(mut egraph: EGraph, desugar: bool) {
+    println!("Welcome to Egglog!");
+    let mut editor = Editor::<()>::new().expect("Failed to start REPL");
+    let history = std::env::var_os("HOME").map(|home| Path::new(&home).join(".egglog_history"));
+    if let Some(history) = &history {
+        // there is no history before the first session
+        let _ = editor.load_history(history);
+    }
+    let mut buffer = String::new();
+    loop {
+        let prompt = if buffer.is_empty() { "> " } else { ". " };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                buffer.push_str(&line);
+                buffer.push('\n');
+                if paren_depth(&buffer) > 0 {
+                    continue;
+                }
+                let input = mem::take(&mut buffer);
+                if input.trim().is_empty() {
+                    continue;
+                }
+                editor.add_history_entry(input.trim_end());
+                let result = if desugar {
+                    (egraph.parse_and_process_program(&input))
+                        .map(|commands| commands.iter().map(ToString::to_string).collect())
+                } else {
+                    egraph.parse_and_run_program(&input)
+                };
+                match result {
+                    Ok(msgs) => msgs.iter().for_each(|msg: &String| println!("{msg}")),
+                    Err(err) => {
+                        log::error!("{}", err);
+                        if matches!(err, Error::TimeLimit) {
+                            exit(&err)
+                        }
+                    }
+                }
+            }
+            Err(ReadlineError::Interrupted) => buffer.clear(),
+            Err(ReadlineError::Eof) => break,
             Err(err) => {
                 log::error!("{}", err);
-                std::process::exit(1)
+                break;
             }
         }
+    }
+    if let Some(history) = &history {
+        if let Err(err) = editor.save_history(history) {
+            log::warn!("Failed to save history to {}: {}", history.display(), err);
+        }
+    }
+}
 
-        // no need to drop the egraph if we are going to exit
-        if idx == args.inputs.len() - 1 {
-            std::mem::forget(egraph)
+/// The number of parentheses left open at the end of `input`, not counting
+/// those in strings and comments.
+fn paren_depth(input: &str) -> i64 {
+    let mut depth = 0;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '"' => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            ';' => {
+                chars.find(|&c| c == '\n');
+            }
+            '#' if chars.next_if_eq(&'|').is_some() => {
+                while let Some(c) = chars.next() {
+                    if c == '|' && chars.next_if_eq(&'#').is_some() {
+                        break;
+                    }
+                }
+            }
+            _ => {}
         }
     }
+    depth
 }
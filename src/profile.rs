@@ -0,0 +1,129 @@
+#[cfg(feature = "serde-1")]
+use serde::Serialize;
+
+use crate::*;
+
+/// The time spent running rules, as returned by [`EGraph::profile`]. Times
+/// are in seconds.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde-1", derive(Serialize))]
+pub struct Profile {
+    /// Every ruleset, in name order.
+    pub rulesets: Vec<RulesetProfile>,
+    /// Every iteration of `run` so far, in order, including those undone by
+    /// `pop` and those of `simplify`.
+    pub iterations: Vec<IterationProfile>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde-1", derive(Serialize))]
+pub struct RulesetProfile {
+    pub name: String,
+    /// Every rule in the ruleset, in name order.
+    pub rules: Vec<RuleProfile>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde-1", derive(Serialize))]
+pub struct RuleProfile {
+    pub name: String,
+    pub matches: usize,
+    pub search_time: f64,
+    pub apply_time: f64,
+    pub times_banned: usize,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde-1", derive(Serialize))]
+pub struct IterationProfile {
+    pub ruleset: String,
+    pub search_time: f64,
+    pub apply_time: f64,
+    pub rebuild_time: f64,
+    /// The number of tuples in the database after rebuilding.
+    pub db_size: usize,
+}
+
+impl EGraph {
+    /// Profile the rules run so far, or since [`EGraph::clear_profile`].
+    /// Unlike the rest of the e-graph, this is not rolled back by `pop`,
+    /// except that rules added since the push are dropped.
+    pub fn profile(&self) -> Profile {
+        let mut rulesets: Vec<RulesetProfile> = (self.rulesets.iter())
+            .map(|(name, rules)| {
+                let mut rules: Vec<RuleProfile> = (rules.iter())
+                    .map(|(name, rule)| RuleProfile {
+                        name: name.to_string(),
+                        matches: rule.matches,
+                        search_time: rule.search_time.as_secs_f64(),
+                        apply_time: rule.apply_time.as_secs_f64(),
+                        times_banned: rule.times_banned,
+                    })
+                    .collect();
+                rules.sort_by(|a, b| a.name.cmp(&b.name));
+                RulesetProfile {
+                    name: name.to_string(),
+                    rules,
+                }
+            })
+            .collect();
+        rulesets.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Profile {
+            rulesets,
+            iterations: self.iterations.clone(),
+        }
+    }
+
+    /// Start a new profile, forgetting the iterations run so far and the
+    /// matches and times of every rule. How often rules were banned is kept,
+    /// since it decides how they are scheduled.
+    pub fn clear_profile(&mut self) {
+        self.iterations.clear();
+        for rule in self
+            .rulesets
+            .values_mut()
+            .flat_map(|rules| rules.values_mut())
+        {
+            rule.matches = 0;
+            rule.search_time = Duration::default();
+            rule.apply_time = Duration::default();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: &str = "(datatype Math (Num i64) (Add Math Math))
+                           (rewrite (Add a b) (Add b a))
+                           (let e (Add (Num 1) (Num 2)))";
+
+    fn matches(profile: &Profile) -> usize {
+        profile.rulesets[0].rules[0].matches
+    }
+
+    #[test]
+    fn test_profile_survives_pop() {
+        let mut egraph = EGraph::default();
+        egraph.parse_and_run_program(PROGRAM).unwrap();
+        egraph.parse_and_run_program("(push) (run 2)").unwrap();
+        let ran = egraph.profile();
+        assert!(!ran.iterations.is_empty());
+        egraph.parse_and_run_program("(pop)").unwrap();
+        let popped = egraph.profile();
+        assert_eq!(popped.iterations.len(), ran.iterations.len());
+        assert_eq!(matches(&popped), matches(&ran));
+
+        egraph.parse_and_run_program("(simplify 3 e)").unwrap();
+        let profile = egraph.profile();
+        assert!(profile.iterations.len() > ran.iterations.len());
+        assert!(matches(&profile) > matches(&ran));
+
+        egraph.clear_profile();
+        let profile = egraph.profile();
+        assert!(profile.iterations.is_empty());
+        assert_eq!(matches(&profile), 0);
+    }
+}
@@ -67,6 +67,7 @@ fn make_rep_primitive_sorts(type_info: &TypeInfo) -> Vec<Command> {
                 merge_action: vec![],
                 default: None,
                 cost: None,
+                unextractable: false,
             })
         })
         .collect()
@@ -87,6 +88,7 @@ fn make_ast_primitives_sorts(type_info: &TypeInfo) -> Vec<Command> {
                 merge_action: vec![],
                 default: None,
                 cost: None,
+                unextractable: false,
             })
         })
         .collect()
@@ -103,6 +105,7 @@ fn make_ast_function(proof_state: &mut ProofState, expr: &NormExpr) -> FunctionD
         merge_action: vec![],
         default: None,
         cost: None,
+        unextractable: false,
     }
 }
 
@@ -146,7 +149,7 @@ fn merge_action(proof_state: &mut ProofState, types: FuncType) -> Vec<Action> {
         format!("(set (EqGraph__ {t1} {t2}) (MakeProofWithAge__ congr_prf__ age__))"),
         format!("(set (EqGraph__ {t2} {t1}) (MakeProofWithAge__ (Flip__ congr_prf__) age__))"),
     ])
-    .map(|s| proof_state.desugar.action_parser.parse(&s).unwrap())
+    .map(|s| proof_state.desugar.parse_action(&s).unwrap())
     .collect()
 }
 
@@ -633,6 +636,7 @@ fn make_rep_function(proof_state: &mut ProofState, expr: &NormExpr) -> FunctionD
         },
         default: None,
         cost: None,
+        unextractable: false,
     }
 }
 
@@ -689,18 +693,11 @@ fn make_rep_command(proof_state: &mut ProofState, lhs: Symbol, expr: &NormExpr)
     proof_state.global_var_ast.insert(lhs, ast_var);
     let rep = make_rep_version(proof_state, expr);
     vec![
+        Command::Action(proof_state.desugar.parse_action(&ast_action).unwrap()),
         Command::Action(
             proof_state
                 .desugar
-                .action_parser
-                .parse(&ast_action)
-                .unwrap(),
-        ),
-        Command::Action(
-            proof_state
-                .desugar
-                .action_parser
-                .parse(&format!(
+                .parse_action(&format!(
                     "(set ({} {})
                          (MakeTrmPrf__ {} (Original__ {})))",
                     rep,
@@ -729,8 +726,7 @@ fn proof_original_action(action: &NormAction, proof_state: &mut ProofState) -> V
                 Command::Action(
                     proof_state
                         .desugar
-                        .action_parser
-                        .parse(&format!(
+                        .parse_action(&format!(
                             "(let {} ({} {}))",
                             ast_var,
                             make_ast_version_prim(proof_state.literal_name(literal)),
@@ -741,8 +737,7 @@ fn proof_original_action(action: &NormAction, proof_state: &mut ProofState) -> V
                 Command::Action(
                     proof_state
                         .desugar
-                        .action_parser
-                        .parse(&format!(
+                        .parse_action(&format!(
                             "(set ({} {})
                          (MakeTrmPrf__ {} (Original__ {})))",
                             make_rep_version_prim(&proof_state.literal_name(literal)),
@@ -761,8 +756,7 @@ fn proof_original_action(action: &NormAction, proof_state: &mut ProofState) -> V
             rep_commands.push(Command::Action(
                 proof_state
                     .desugar
-                    .action_parser
-                    .parse(&format!(
+                    .parse_action(&format!(
                         "(set (EqGraph__ {} {}) (MakeProofWithAge__ (OriginalEq__ {} {}) 0))",
                         proof_state.global_var_ast[&fresh],
                         proof_state.global_var_ast[var],
@@ -777,8 +771,7 @@ fn proof_original_action(action: &NormAction, proof_state: &mut ProofState) -> V
             vec![Command::Action(
                 proof_state
                     .desugar
-                    .action_parser
-                    .parse(&format!(
+                    .parse_action(&format!(
                         "(set (EqGraph__ {} {}) (MakeProofWithAge__ (OriginalEq__ {} {}) 0))",
                         proof_state.global_var_ast[var1],
                         proof_state.global_var_ast[var2],
@@ -816,8 +809,12 @@ fn instrument_schedule(schedule: &NormSchedule) -> Schedule {
 }
 
 impl ProofState {
-    pub fn parse_program(&self, input: &str) -> Result<Vec<Command>, Error> {
-        self.desugar.parse_program(input)
+    pub fn parse_program(
+        &self,
+        filename: Option<String>,
+        input: &str,
+    ) -> Result<Vec<(Span, Command)>, Error> {
+        self.desugar.parse_spanned_program(filename, input)
     }
 
     // TODO we need to also instrument merge actions and merge because they can add new terms that need representatives
@@ -887,9 +884,10 @@ impl ProofState {
     pub(crate) fn proof_header(&self) -> Vec<Command> {
         let str = include_str!("proofheader.egg");
         let rest_of_header = setup_primitives();
-        self.parse_program(str)
+        self.parse_program(None, str)
             .unwrap()
             .into_iter()
+            .map(|(_, command)| command)
             .chain(rest_of_header)
             .collect()
     }
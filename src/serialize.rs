@@ -0,0 +1,402 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// The contents of an e-graph, as written by [`EGraph::serialize`].
+///
+/// Sorts, primitives and compiled rules can't be written out, so instead the
+/// snapshot holds the commands that declared them. Loading replays those
+/// without running any actions, then fills in the tables, the union-find and
+/// the interners of container sorts.
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    declarations: Vec<Declaration>,
+    proofs_enabled: bool,
+    timestamp: u32,
+    number_underscores: usize,
+    next_fresh: usize,
+    next_command_id: usize,
+    global_var_ast: Vec<(String, String)>,
+    ast_funcs_created: Vec<String>,
+    /// The `define-macro` commands of the macros defined so far.
+    macros: Vec<String>,
+    /// The canonical id of every id.
+    roots: Vec<usize>,
+    n_unions: usize,
+    interned: Vec<(String, Vec<Vec<SavedValue>>)>,
+    functions: Vec<(String, Vec<Row>)>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Declaration {
+    command: String,
+    /// How far along a rule is, so that resuming doesn't search old matches
+    /// again.
+    rule: Option<RuleProgress>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RuleProgress {
+    matches: usize,
+    times_banned: usize,
+    banned_until: usize,
+    todo_timestamp: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Row {
+    inputs: Vec<SavedValue>,
+    output: SavedValue,
+    timestamp: u32,
+}
+
+/// A [`Value`] that can be loaded by another process. Strings are interned in
+/// a global symbol table, so they are saved as text.
+#[derive(Serialize, Deserialize)]
+enum SavedValue {
+    Bits(String, u64),
+    String(String),
+}
+
+/// Whether `command` declares something that has to exist before the contents
+/// of an e-graph can be restored.
+pub(crate) fn is_declaration(command: &NCommand) -> bool {
+    matches!(
+        command,
+        NCommand::SetOption { .. }
+            | NCommand::Sort(..)
+            | NCommand::Function(_)
+            | NCommand::AddRuleset(_)
+            | NCommand::NormRule { .. }
+            | NCommand::NormAction(
+                NormAction::Let(..) | NormAction::LetVar(..) | NormAction::LetLit(..)
+            )
+    )
+}
+
+impl EGraph {
+    /// Write out the e-graph as JSON, to be restored by
+    /// [`EGraph::deserialize`]. Settings that are not set by commands (such as
+    /// the cost model), explanations and the scopes opened by `push` are not
+    /// saved.
+    pub fn serialize(&mut self) -> Result<String, Error> {
+        self.rebuild()?;
+
+        let declarations = self
+            .declarations
+            .iter()
+            .map(|command| Declaration {
+                command: command.to_command().to_string(),
+                rule: match command {
+                    NCommand::NormRule {
+                        ruleset, ref rule, ..
+                    } => {
+                        let rule =
+                            &self.rulesets[ruleset][&Symbol::from(rule.to_rule().to_string())];
+                        Some(RuleProgress {
+                            matches: rule.matches,
+                            times_banned: rule.times_banned,
+                            banned_until: rule.banned_until,
+                            todo_timestamp: rule.todo_timestamp,
+                        })
+                    }
+                    _ => None,
+                },
+            })
+            .collect();
+
+        let interned = self
+            .proof_state
+            .type_info
+            .sorts
+            .values()
+            .filter(|sort| sort.num_interned() > 0)
+            .map(|sort| {
+                let values = sort
+                    .interned()
+                    .into_iter()
+                    .map(|values| values.into_iter().map(|v| self.save_value(v)).collect())
+                    .collect();
+                (sort.name().to_string(), values)
+            })
+            .collect();
+
+        let functions = self
+            .functions
+            .iter()
+            .map(|(name, function)| {
+                let rows = function
+                    .nodes
+                    .iter()
+                    .map(|(inputs, output)| Row {
+                        inputs: inputs.iter().map(|v| self.save_value(*v)).collect(),
+                        output: self.save_value(output.value),
+                        timestamp: output.timestamp,
+                    })
+                    .collect();
+                (name.to_string(), rows)
+            })
+            .collect();
+
+        let desugar = &self.proof_state.desugar;
+        let mut macros: Vec<String> = (desugar.macros.iter())
+            .map(|(name, mac)| mac.to_command(*name).to_string())
+            .collect();
+        macros.sort();
+        let snapshot = Snapshot {
+            declarations,
+            proofs_enabled: self.proofs_enabled,
+            timestamp: self.timestamp,
+            number_underscores: desugar.number_underscores,
+            next_fresh: desugar.next_fresh,
+            next_command_id: desugar.next_command_id,
+            global_var_ast: (self.proof_state.global_var_ast.iter())
+                .map(|(var, ast)| (var.to_string(), ast.to_string()))
+                .collect(),
+            ast_funcs_created: (self.proof_state.ast_funcs_created.iter())
+                .map(|name| name.to_string())
+                .collect(),
+            macros,
+            roots: (0..self.unionfind.size())
+                .map(|id| usize::from(self.find(Id::from(id))))
+                .collect(),
+            n_unions: self.unionfind.n_unions(),
+            interned,
+            functions,
+        };
+        Ok(serde_json::to_string(&snapshot).expect("snapshots can always be serialized"))
+    }
+
+    /// Load an e-graph written by [`EGraph::serialize`].
+    pub fn deserialize(data: &str) -> Result<EGraph, Error> {
+        let mut egraph = EGraph::default();
+        egraph.restore(data)?;
+        Ok(egraph)
+    }
+
+    /// Write the e-graph to `path`. The file is replaced only once it has
+    /// been written completely, so an interrupted save keeps the last one.
+    pub(crate) fn save(&mut self, path: &Path) -> Result<(), Error> {
+        let data = self.serialize()?;
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, data).map_err(|e| Error::IoError(tmp.clone().into(), e))?;
+        std::fs::rename(&tmp, path).map_err(|e| Error::IoError(path.into(), e))
+    }
+
+    /// Replace the e-graph by the one saved in `path`. The scopes opened by
+    /// `push` could not be popped afterwards, so there must be none.
+    pub(crate) fn load(&mut self, path: &Path) -> Result<(), Error> {
+        if !self.egraphs.is_empty() {
+            return Err(Error::LoadInsidePush(path.into()));
+        }
+        let data = std::fs::read_to_string(path).map_err(|e| Error::IoError(path.into(), e))?;
+        self.restore(&data)
+    }
+
+    /// Replace everything but the settings that are not set by commands with
+    /// the snapshot in `data`.
+    fn restore(&mut self, data: &str) -> Result<(), Error> {
+        let snapshot: Snapshot =
+            serde_json::from_str(data).map_err(|e| Error::SnapshotError(e.to_string()))?;
+
+        let mut egraph = EGraph {
+            test_proofs: self.test_proofs,
+            fact_directory: self.fact_directory.clone(),
+            seminaive: self.seminaive,
+            cost_model: self.cost_model.clone(),
+            ..EGraph::default()
+        };
+        egraph.set_underscores_for_desugaring(snapshot.number_underscores);
+        // The saved declarations are already desugared and use fresh names
+        // from before `next_fresh`, so desugaring them again must not hand
+        // those names out a second time.
+        let desugar = &mut egraph.proof_state.desugar;
+        desugar.next_fresh = snapshot.next_fresh;
+        desugar.next_command_id = snapshot.next_command_id;
+        for declaration in &snapshot.declarations {
+            egraph.replay(declaration)?;
+        }
+        for source in &snapshot.macros {
+            let commands = egraph.parse_program(source)?;
+            egraph
+                .proof_state
+                .desugar
+                .desugar_program(commands, None, false, false)?;
+        }
+
+        egraph.proofs_enabled = snapshot.proofs_enabled;
+        egraph.timestamp = snapshot.timestamp;
+        let desugar = &mut egraph.proof_state.desugar;
+        desugar.next_fresh = desugar.next_fresh.max(snapshot.next_fresh);
+        desugar.next_command_id = desugar.next_command_id.max(snapshot.next_command_id);
+        egraph.proof_state.global_var_ast = (snapshot.global_var_ast.iter())
+            .map(|(var, ast)| (Symbol::from(var), Symbol::from(ast)))
+            .collect();
+        egraph.proof_state.ast_funcs_created = (snapshot.ast_funcs_created.iter())
+            .map(Symbol::from)
+            .collect();
+
+        let roots: Vec<Id> = snapshot.roots.iter().map(|&id| Id::from(id)).collect();
+        if roots.iter().any(|&root| usize::from(root) >= roots.len()) {
+            return Err(Error::SnapshotError("id out of range".into()));
+        }
+        egraph.unionfind = UnionFind::from_roots(&roots, snapshot.n_unions);
+
+        for (name, values) in &snapshot.interned {
+            let sort = egraph.snapshot_sort(name)?;
+            let values = values
+                .iter()
+                .map(|values| values.iter().map(|v| egraph.load_value(v)).collect())
+                .collect::<Result<_, _>>()?;
+            sort.set_interned(values);
+        }
+
+        for (name, rows) in &snapshot.functions {
+            let rows = rows
+                .iter()
+                .map(|row| {
+                    let inputs = (row.inputs.iter())
+                        .map(|v| egraph.load_value(v))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    Ok((inputs, egraph.load_value(&row.output)?, row.timestamp))
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            let function = egraph
+                .functions
+                .get_mut(&Symbol::from(name))
+                .ok_or_else(|| Error::SnapshotError(format!("undeclared function {name}")))?;
+            for (inputs, output, timestamp) in rows {
+                function.insert_internal(&inputs, output, timestamp, false);
+            }
+        }
+
+        *self = egraph;
+        Ok(())
+    }
+
+    /// Run a saved declaration, without evaluating the definitions of globals:
+    /// their values are restored with the tables.
+    fn replay(&mut self, declaration: &Declaration) -> Result<(), Error> {
+        let commands = self.parse_program(&declaration.command)?;
+        let commands = self
+            .proof_state
+            .desugar
+            .desugar_program(commands, None, false, false)?;
+        for command in commands {
+            self.proof_state.type_info.typecheck_command(&command)?;
+            match command.command {
+                NCommand::NormAction(
+                    NormAction::Let(name, _)
+                    | NormAction::LetVar(name, _)
+                    | NormAction::LetLit(name, _),
+                ) => {
+                    let sort = self.proof_state.type_info.global_types[&name].clone();
+                    self.declare_function(
+                        &FunctionDecl {
+                            name,
+                            schema: Schema {
+                                input: vec![],
+                                output: sort.name(),
+                            },
+                            default: None,
+                            merge: None,
+                            merge_action: vec![],
+                            cost: Some(HIGH_COST),
+                            unextractable: false,
+                        },
+                        true,
+                    )?;
+                    self.declarations.push(command.command);
+                }
+                NCommand::NormRule {
+                    ruleset, ref rule, ..
+                } => {
+                    let name = self.add_rule(rule.to_rule(), ruleset)?;
+                    if let Some(progress) = &declaration.rule {
+                        let rule = self
+                            .rulesets
+                            .get_mut(&ruleset)
+                            .unwrap()
+                            .get_mut(&name)
+                            .unwrap();
+                        rule.matches = progress.matches;
+                        rule.times_banned = progress.times_banned;
+                        rule.banned_until = progress.banned_until;
+                        rule.todo_timestamp = progress.todo_timestamp;
+                    }
+                    self.declarations.push(command.command);
+                }
+                command => {
+                    self.run_command(command, false)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn snapshot_sort(&self, name: &str) -> Result<ArcSort, Error> {
+        (self
+            .proof_state
+            .type_info
+            .sorts
+            .get(&Symbol::from(name))
+            .cloned())
+        .ok_or_else(|| Error::SnapshotError(format!("undeclared sort {name}")))
+    }
+
+    fn save_value(&self, value: Value) -> SavedValue {
+        let sort = self.get_sort(&value).unwrap().clone();
+        match sort.as_arc_any().downcast::<StringSort>() {
+            Ok(sort) => SavedValue::String(Symbol::load(&sort, &value).to_string()),
+            Err(_) => SavedValue::Bits(value.tag.to_string(), value.bits),
+        }
+    }
+
+    fn load_value(&self, value: &SavedValue) -> Result<Value, Error> {
+        Ok(match value {
+            SavedValue::Bits(tag, bits) => {
+                let sort = self.snapshot_sort(tag)?;
+                Value {
+                    tag: sort.name(),
+                    bits: *bits,
+                }
+            }
+            SavedValue::String(s) => Value::from(Symbol::from(s)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut egraph = EGraph::default();
+        egraph
+            .parse_and_run_program(
+                "(datatype Math (Num i64) (Add Math Math))
+                 (sort MathSet (Set Math))
+                 (function members (Math) MathSet :merge (set-union old new))
+                 (rewrite (Add x y) (Add y x))
+                 (define e (Add (Num 1) (Num 2)))
+                 (set (members e) (set-insert (set-empty) (Num 3)))
+                 (run 1)",
+            )
+            .unwrap();
+        let data = egraph.serialize().unwrap();
+
+        let mut loaded = EGraph::deserialize(&data).unwrap();
+        loaded
+            .parse_and_run_program(
+                "(check (= e (Add (Num 2) (Num 1))))
+                 (check (set-contains (members e) (Num 3)))
+                 (run 1)
+                 (check (= (Add (Num 2) (Num 1)) (Add (Num 1) (Num 2))))",
+            )
+            .unwrap();
+    }
+}
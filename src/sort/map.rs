@@ -127,6 +127,28 @@ impl Sort for MapSort {
         });
     }
 
+    fn num_interned(&self) -> usize {
+        self.maps.lock().unwrap().len()
+    }
+
+    fn truncate_interned(&self, len: usize) {
+        self.maps.lock().unwrap().truncate(len)
+    }
+
+    fn interned(&self) -> Vec<Vec<Value>> {
+        let maps = self.maps.lock().unwrap();
+        maps.iter()
+            .map(|map| map.iter().flat_map(|(k, v)| [*k, *v]).collect())
+            .collect()
+    }
+
+    fn set_interned(&self, values: Vec<Vec<Value>>) {
+        *self.maps.lock().unwrap() = values
+            .into_iter()
+            .map(|kvs| kvs.chunks(2).map(|kv| (kv[0], kv[1])).collect())
+            .collect();
+    }
+
     fn make_expr(&self, value: Value) -> Expr {
         let map = ValueMap::load(self, &value);
         let mut expr = Expr::call("map-empty", []);
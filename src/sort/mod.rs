@@ -61,6 +61,31 @@ pub trait Sort: Any + Send + Sync + Debug {
     }
 
     fn make_expr(&self, value: Value) -> Expr;
+
+    /// The number of values interned so far, for sorts whose values are
+    /// indices into an interner.
+    fn num_interned(&self) -> usize {
+        0
+    }
+
+    /// Forget all but the first `len` interned values. This is only sound if
+    /// no live value refers to the others, e.g. after `pop` undid everything
+    /// since they were interned.
+    fn truncate_interned(&self, len: usize) {
+        let _ = len;
+    }
+
+    /// The values interned so far, in order, each flattened into the values
+    /// it is made of.
+    fn interned(&self) -> Vec<Vec<Value>> {
+        vec![]
+    }
+
+    /// Replace the interned values with `values`, as returned by
+    /// [`Sort::interned`], so that indices into the interner are preserved.
+    fn set_interned(&self, values: Vec<Vec<Value>>) {
+        let _ = values;
+    }
 }
 
 #[derive(Debug)]
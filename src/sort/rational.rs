@@ -105,6 +105,28 @@ impl Sort for RationalSort {
         add_primitives!(eg, "<=" = |a: R, b: R| -> Opt { if a <= b {Some(())} else {None} }); 
         add_primitives!(eg, ">=" = |a: R, b: R| -> Opt { if a >= b {Some(())} else {None} }); 
    }
+    fn num_interned(&self) -> usize {
+        self.rats.lock().unwrap().len()
+    }
+
+    fn truncate_interned(&self, len: usize) {
+        self.rats.lock().unwrap().truncate(len)
+    }
+
+    fn interned(&self) -> Vec<Vec<Value>> {
+        let rats = self.rats.lock().unwrap();
+        rats.iter()
+            .map(|rat| vec![Value::from(*rat.numer()), Value::from(*rat.denom())])
+            .collect()
+    }
+
+    fn set_interned(&self, values: Vec<Vec<Value>>) {
+        *self.rats.lock().unwrap() = values
+            .into_iter()
+            .map(|parts| R::new_raw(parts[0].bits as i64, parts[1].bits as i64))
+            .collect();
+    }
+
     fn make_expr(&self, value: Value) -> Expr {
         assert!(value.tag == self.name());
         let rat = R::load(self, &value);
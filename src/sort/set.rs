@@ -129,6 +129,28 @@ impl Sort for SetSort {
         });
     }
 
+    fn num_interned(&self) -> usize {
+        self.sets.lock().unwrap().len()
+    }
+
+    fn truncate_interned(&self, len: usize) {
+        self.sets.lock().unwrap().truncate(len)
+    }
+
+    fn interned(&self) -> Vec<Vec<Value>> {
+        let sets = self.sets.lock().unwrap();
+        sets.iter()
+            .map(|set| set.iter().copied().collect())
+            .collect()
+    }
+
+    fn set_interned(&self, values: Vec<Vec<Value>>) {
+        *self.sets.lock().unwrap() = values
+            .into_iter()
+            .map(|set| set.into_iter().collect())
+            .collect();
+    }
+
     fn make_expr(&self, value: Value) -> Expr {
         let set = ValueSet::load(self, &value);
         let mut expr = Expr::call("set-empty", []);
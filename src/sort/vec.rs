@@ -120,6 +120,22 @@ impl Sort for VecSort {
         });
     }
 
+    fn num_interned(&self) -> usize {
+        self.vecs.lock().unwrap().len()
+    }
+
+    fn truncate_interned(&self, len: usize) {
+        self.vecs.lock().unwrap().truncate(len)
+    }
+
+    fn interned(&self) -> Vec<Vec<Value>> {
+        self.vecs.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn set_interned(&self, values: Vec<Vec<Value>>) {
+        *self.vecs.lock().unwrap() = values.into_iter().collect();
+    }
+
     fn make_expr(&self, value: Value) -> Expr {
         let vec = ValueVec::load(self, &value);
         let mut expr = Expr::call("vec-empty", []);
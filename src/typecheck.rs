@@ -662,6 +662,7 @@ impl EGraph {
                             }
                             None if out.is_eq_sort() => {
                                 let id = self.unionfind.make_set();
+                                self.unionfind.set_origin(id, *f, values);
                                 let value = Value::from_id(out.name(), id);
                                 function.insert(values, value, ts);
                                 value
@@ -750,11 +751,10 @@ impl EGraph {
                     let new_len = stack.len() - arity;
                     let values = &stack[new_len..];
                     let sort = values[0].tag;
-                    let first = self.unionfind.find(Id::from(values[0].bits as usize));
-                    values[1..].iter().fold(first, |a, b| {
-                        let b = self.unionfind.find(Id::from(b.bits as usize));
-                        self.unionfind.union(a, b, sort)
-                    });
+                    let first = Id::from(values[0].bits as usize);
+                    for b in &values[1..] {
+                        self.unionfind.union(first, Id::from(b.bits as usize), sort);
+                    }
                     stack.truncate(new_len);
                 }
                 Instruction::Panic(msg) => panic!("Panic: {}", msg),
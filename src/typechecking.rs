@@ -100,12 +100,10 @@ impl TypeInfo {
         self.primitives.entry(prim.name()).or_default().push(prim);
     }
 
-    pub(crate) fn typecheck_program(
-        &mut self,
-        program: &Vec<NormCommand>,
-    ) -> Result<(), TypeError> {
+    pub(crate) fn typecheck_program(&mut self, program: &Vec<NormCommand>) -> Result<(), Error> {
         for command in program {
-            self.typecheck_command(command)?;
+            self.typecheck_command(command)
+                .map_err(|e| Error::from(e).with_span(command.metadata.span.as_ref()))?;
         }
 
         Ok(())
@@ -2,20 +2,81 @@
 //! halving for compression.
 //!
 //! This implementation uses interior mutability for `find`.
+//!
+//! Changes made after a checkpoint, including path compression, are recorded
+//! in an undo log so that they can be rolled back.
 use crate::util::HashMap;
 use crate::{Id, Symbol, Value};
 
-use std::cell::Cell;
-use std::fmt::Debug;
+use std::cell::{Cell, RefCell};
+use std::fmt::{self, Debug, Display};
 use std::mem;
 
 #[derive(Debug, Clone, Default)]
-#[cfg_attr(feature = "serde-1", derive(serde::Serialize, serde::Deserialize))]
 pub struct UnionFind {
     parents: Vec<Cell<Id>>,
     n_unions: usize,
     recent_ids: HashMap<Symbol, Vec<Id>>,
     staged_ids: HashMap<Symbol, Vec<Id>>,
+    explain: Option<Explain>,
+    /// Parents and proof edges overwritten while there is a checkpoint.
+    undo: RefCell<Vec<Undo>>,
+    checkpoints: Vec<Checkpoint>,
+}
+
+/// A parent or proof edge from before it was overwritten.
+#[derive(Debug, Clone)]
+enum Undo {
+    /// The id had the given parent.
+    Parent(Id, Id),
+    /// The id had the given edge in the proof forest.
+    Edge(Id, Option<(Id, Justification)>),
+}
+
+#[derive(Debug, Clone)]
+struct Checkpoint {
+    undo_len: usize,
+    size: usize,
+    n_unions: usize,
+    recent_ids: HashMap<Symbol, Vec<Id>>,
+    staged_ids: HashMap<Symbol, Vec<Id>>,
+}
+
+/// Why two ids were unioned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Justification {
+    /// A top-level action, such as `(union a b)` or `(set (f a) b)`.
+    Action,
+    /// A rule fired, matching its variables to the given values.
+    Rule(Symbol, Vec<(Symbol, Value)>),
+    /// The ids are outputs of the given function on equivalent inputs, or were
+    /// merged by its `:merge` or `:on_merge`.
+    Congruence(Symbol),
+}
+
+impl Display for Justification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Justification::Action => write!(f, "action"),
+            Justification::Rule(name, _) => write!(f, "rule {name}"),
+            Justification::Congruence(func) => write!(f, "congruence of {func}"),
+        }
+    }
+}
+
+/// The proof forest kept when explanations are enabled. Every union adds an
+/// edge between the two ids it was given (not their representatives), so the
+/// path between two equivalent ids is a chain of justified unions.
+#[derive(Debug, Clone)]
+struct Explain {
+    /// The id each id was unioned with and why, or `None` for a tree's root.
+    edges: Vec<Option<(Id, Justification)>>,
+    /// The function and inputs each id was first created for.
+    origins: Vec<Option<(Symbol, Vec<Value>)>>,
+    /// The ids created for each function, oldest first.
+    by_function: HashMap<Symbol, Vec<Id>>,
+    /// The justification recorded for the next unions.
+    current: Justification,
 }
 
 impl UnionFind {
@@ -25,13 +86,116 @@ impl UnionFind {
         self.n_unions
     }
 
+    /// The number of [`Id`]s created so far.
+    pub fn size(&self) -> usize {
+        self.parents.len()
+    }
+
+    /// Create a union-find over `roots.len()` ids, where each id `i` is in the
+    /// class of `roots[i]`. Every element of `roots` must be its own root.
+    #[cfg(feature = "serde-1")]
+    pub fn from_roots(roots: &[Id], n_unions: usize) -> Self {
+        debug_assert!(roots.iter().all(|&id| roots[usize::from(id)] == id));
+        UnionFind {
+            parents: roots.iter().copied().map(Cell::new).collect(),
+            n_unions,
+            ..Default::default()
+        }
+    }
+
     /// Create a fresh [`Id`].
     pub fn make_set(&mut self) -> Id {
         let res = Id::from(self.parents.len());
         self.parents.push(Cell::new(res));
+        if let Some(explain) = &mut self.explain {
+            explain.edges.push(None);
+            explain.origins.push(None);
+        }
         res
     }
 
+    /// Start recording why ids are unioned. Unions performed before this are
+    /// not explained.
+    pub fn enable_explanations(&mut self) {
+        if self.explain.is_none() {
+            self.explain = Some(Explain {
+                edges: vec![None; self.parents.len()],
+                origins: vec![None; self.parents.len()],
+                by_function: Default::default(),
+                current: Justification::Action,
+            });
+        }
+    }
+
+    pub fn explanations_enabled(&self) -> bool {
+        self.explain.is_some()
+    }
+
+    /// Set the justification recorded for the unions performed from now on.
+    /// `justification` is only called if explanations are enabled.
+    pub fn set_justification(&mut self, justification: impl FnOnce() -> Justification) {
+        if let Some(explain) = &mut self.explain {
+            explain.current = justification();
+        }
+    }
+
+    /// Record that `id` was created for the e-node `function(inputs)`, if
+    /// explanations are enabled.
+    pub fn set_origin(&mut self, id: Id, function: Symbol, inputs: &[Value]) {
+        if let Some(explain) = &mut self.explain {
+            explain.origins[usize::from(id)] = Some((function, inputs.to_vec()));
+            explain.by_function.entry(function).or_default().push(id);
+        }
+    }
+
+    /// The e-node `id` was created for, as recorded by [`set_origin`].
+    ///
+    /// [`set_origin`]: UnionFind::set_origin
+    pub fn origin(&self, id: Id) -> Option<(Symbol, &[Value])> {
+        let (function, inputs) = self.explain.as_ref()?.origins[usize::from(id)].as_ref()?;
+        Some((*function, inputs))
+    }
+
+    /// The ids created for e-nodes of `function`, oldest first.
+    pub fn ids_with_origin(&self, function: Symbol) -> &[Id] {
+        self.explain
+            .as_ref()
+            .and_then(|explain| explain.by_function.get(&function))
+            .map_or(&[], |ids| ids.as_slice())
+    }
+
+    /// The chain of unions connecting `id1` to `id2`, as a list of steps
+    /// `(from, to, justification)`. Returns `None` if explanations are not
+    /// enabled or the ids were not made equivalent while they were.
+    pub fn explain(&self, id1: Id, id2: Id) -> Option<Vec<(Id, Id, Justification)>> {
+        let explain = self.explain.as_ref()?;
+        let ancestors = |mut id: Id| {
+            let mut path = vec![id];
+            while let Some((parent, _)) = &explain.edges[usize::from(id)] {
+                id = *parent;
+                path.push(id);
+            }
+            path
+        };
+        let mut path1 = ancestors(id1);
+        let mut path2 = ancestors(id2);
+        if path1.last() != path2.last() {
+            return None;
+        }
+        // strip the common part of the paths, except for the nearest common
+        // ancestor
+        while path1.len() > 1 && path2.len() > 1 && path1[path1.len() - 2] == path2[path2.len() - 2]
+        {
+            path1.pop();
+            path2.pop();
+        }
+
+        let edge = |id: Id| explain.edges[usize::from(id)].as_ref().unwrap().1.clone();
+        let mut steps: Vec<_> = path1.windows(2).map(|w| (w[0], w[1], edge(w[0]))).collect();
+        steps.extend(path2.windows(2).rev().map(|w| (w[1], w[0], edge(w[0]))));
+        Some(steps)
+    }
+
     /// The number of ids that recently stopped being canonical.
     pub fn new_ids(&self, sort_filter: impl Fn(Symbol) -> bool) -> usize {
         self.recent_ids
@@ -68,18 +232,68 @@ impl UnionFind {
         ids.iter().copied()
     }
 
+    /// Log overwritten parents and edges from now on.
+    pub fn checkpoint(&mut self) {
+        self.checkpoints.push(Checkpoint {
+            undo_len: self.undo.get_mut().len(),
+            size: self.parents.len(),
+            n_unions: self.n_unions,
+            recent_ids: self.recent_ids.clone(),
+            staged_ids: self.staged_ids.clone(),
+        });
+    }
+
+    /// Put the parents and edges back as they were at the last checkpoint,
+    /// forgetting the ids created since.
+    pub fn rollback(&mut self) {
+        let checkpoint = self
+            .checkpoints
+            .pop()
+            .expect("no checkpoint to roll back to");
+        let undo = self.undo.get_mut();
+        while undo.len() > checkpoint.undo_len {
+            match undo.pop().unwrap() {
+                Undo::Parent(id, parent) => self.parents[usize::from(id)].set(parent),
+                Undo::Edge(id, edge) => {
+                    self.explain.as_mut().unwrap().edges[usize::from(id)] = edge;
+                }
+            }
+        }
+        self.parents.truncate(checkpoint.size);
+        if let Some(explain) = &mut self.explain {
+            explain.edges.truncate(checkpoint.size);
+            explain.origins.truncate(checkpoint.size);
+            for ids in explain.by_function.values_mut() {
+                ids.truncate(ids.partition_point(|&id| usize::from(id) < checkpoint.size));
+            }
+            explain.current = Justification::Action;
+        }
+        self.n_unions = checkpoint.n_unions;
+        self.recent_ids = checkpoint.recent_ids;
+        self.staged_ids = checkpoint.staged_ids;
+    }
+
+    /// Drop the last checkpoint, keeping the changes made since.
+    pub fn commit(&mut self) {
+        self.checkpoints.pop().expect("no checkpoint to commit");
+        if self.checkpoints.is_empty() {
+            self.undo.get_mut().clear();
+        }
+    }
+
     /// Look up the canonical representative for the given [`Id`].
     pub fn find(&self, id: Id) -> Id {
-        let mut cur = self.parent(id);
+        let mut cur = id;
         loop {
-            let next = self.parent(cur.get());
-            if cur.get() == next.get() {
-                return cur.get();
+            let parent = self.parent(cur).get();
+            let grandparent = self.parent(parent).get();
+            if parent == grandparent {
+                return parent;
             }
             // Path halving
-            let grand = self.parent(next.get());
-            cur.set(grand.get());
-            cur = grand;
+            let next = self.parent(grandparent).get();
+            self.set_parent(cur, next);
+            cur = grandparent;
         }
     }
 
@@ -120,20 +334,64 @@ impl UnionFind {
     }
 
     fn do_union(&mut self, id1: Id, id2: Id) -> (Id, Option<Id>) {
-        let id1 = self.find(id1);
-        let id2 = self.find(id2);
-        if id1 != id2 {
-            self.parent(id2).set(id1);
+        let root1 = self.find(id1);
+        let root2 = self.find(id2);
+        if root1 != root2 {
+            self.set_parent(root2, root1);
             self.n_unions += 1;
-            (id1, Some(id2))
+            if self.explain.is_some() {
+                self.add_edge(id2, id1);
+            }
+            (root1, Some(root2))
         } else {
-            (id1, None)
+            (root1, None)
         }
     }
 
     fn parent(&self, id: Id) -> &Cell<Id> {
         &self.parents[usize::from(id)]
     }
+
+    fn set_parent(&self, id: Id, parent: Id) {
+        let cell = self.parent(id);
+        if !self.checkpoints.is_empty() {
+            self.undo.borrow_mut().push(Undo::Parent(id, cell.get()));
+        }
+        cell.set(parent);
+    }
+
+    /// Connect the proof trees of `from` and `to` by making `from` the root of
+    /// its tree and pointing it at `to`.
+    fn add_edge(&mut self, from: Id, to: Id) {
+        let mut prev: Option<(Id, Justification)> = None;
+        let mut id = from;
+        loop {
+            let next = self.set_edge(id, prev.take());
+            match next {
+                Some((parent, justification)) => {
+                    prev = Some((id, justification));
+                    id = parent;
+                }
+                None => break,
+            }
+        }
+        let current = self.explain.as_ref().unwrap().current.clone();
+        self.set_edge(from, Some((to, current)));
+    }
+
+    /// Replace the proof forest edge of `id`, returning the old one.
+    fn set_edge(
+        &mut self,
+        id: Id,
+        edge: Option<(Id, Justification)>,
+    ) -> Option<(Id, Justification)> {
+        let explain = self.explain.as_mut().unwrap();
+        let old = mem::replace(&mut explain.edges[usize::from(id)], edge);
+        if !self.checkpoints.is_empty() {
+            self.undo.get_mut().push(Undo::Edge(id, old.clone()));
+        }
+        old
+    }
 }
 
 #[cfg(test)]
@@ -176,4 +434,44 @@ mod tests {
         let expected = vec![0, 0, 0, 0, 4, 5, 6, 6, 6, 6];
         assert_eq!(uf.parents, ids(expected));
     }
+
+    #[test]
+    fn explain() {
+        let id = Id::from;
+        let sort = Symbol::from("S");
+        let rule = |name: &str| Justification::Rule(name.into(), vec![]);
+
+        let mut uf = UnionFind::default();
+        uf.make_set();
+        uf.enable_explanations();
+        for _ in 1..6 {
+            uf.make_set();
+        }
+
+        uf.set_justification(|| rule("a"));
+        uf.union(id(0), id(1), sort);
+        uf.set_justification(|| rule("b"));
+        uf.union(id(2), id(3), sort);
+        uf.set_justification(|| Justification::Action);
+        uf.union(id(3), id(1), sort);
+        uf.union(id(4), id(5), sort);
+
+        assert_eq!(uf.explain(id(2), id(2)), Some(vec![]));
+        assert_eq!(
+            uf.explain(id(0), id(2)),
+            Some(vec![
+                (id(0), id(1), rule("a")),
+                (id(1), id(3), Justification::Action),
+                (id(3), id(2), rule("b")),
+            ])
+        );
+        assert_eq!(
+            uf.explain(id(3), id(0)),
+            Some(vec![
+                (id(3), id(1), Justification::Action),
+                (id(1), id(0), rule("a")),
+            ])
+        );
+        assert_eq!(uf.explain(id(0), id(4)), None);
+    }
 }
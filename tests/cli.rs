@@ -0,0 +1,197 @@
+use instant::{Duration, Instant};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+const TMP: &str = env!("CARGO_TARGET_TMPDIR");
+
+/// Run `egg-smol` with `args` on `program`, written to a file called `name`.
+fn run(name: &str, args: &[&str], program: &str) -> Output {
+    let path = Path::new(TMP).join(format!("cli-{name}.egg"));
+    std::fs::write(&path, program).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_egg-smol"))
+        .args(args)
+        .arg(&path)
+        .output()
+        .unwrap()
+}
+
+/// Run the REPL with `args`, entering `input`.
+fn repl(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_egg-smol"))
+        .args(args)
+        .env("HOME", TMP)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(input.as_bytes()).unwrap();
+    drop(stdin);
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+const WRONG: &str = "(datatype Math (Num i64))
+                     (check (= (Num 1) (Num 2)))";
+
+/// Adds a bigger term in every iteration, forever.
+const GROWING: &str = "(datatype N (Z) (S N))
+                       (let one (S (Z)))
+                       (rule ((= e (S x))) ((S e)))
+                       (run 1000000000)";
+
+#[test]
+fn desugar_prints_commands_without_running_them() {
+    let output = run("desugar", &["--desugar"], WRONG);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("(check (= "));
+
+    let output = run("wrong", &[], WRONG);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn desugar_applies_to_the_repl() {
+    let output = repl(&["--desugar"], WRONG);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("(check (= "));
+    assert!(!stderr(&output).contains("Check failed"));
+}
+
+#[test]
+fn repl_prints_results_even_when_quiet() {
+    let input = "(datatype Math (Num i64))\n(extract (Num 1))\n";
+    let output = repl(&["--quiet"], input);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Extracted with cost 2: (Num 1)"));
+}
+
+#[test]
+fn quiet_only_logs_problems() {
+    let output = run("quiet", &["--quiet"], "(datatype Math (Num i64))");
+    assert!(output.status.success());
+    assert_eq!(stderr(&output), "");
+
+    let output = run("quiet-wrong", &["--quiet"], WRONG);
+    assert!(stderr(&output).contains("Check failed"));
+}
+
+#[test]
+fn time_limit_stops_running_rules() {
+    let start = Instant::now();
+    let output = run("time-limit", &["--quiet", "--time-limit", "0.5"], GROWING);
+    assert!(start.elapsed() < Duration::from_secs(20));
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("Time limit exceeded"));
+}
+
+#[test]
+fn node_limit_stops_running_rules() {
+    let output = run("node-limit", &["--quiet", "--node-limit", "100"], GROWING);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Node limit reached"));
+}
+
+#[test]
+fn shared_profiles_are_per_file() {
+    let first = Path::new(TMP).join("cli-profile-first.egg");
+    let second = Path::new(TMP).join("cli-profile-second.egg");
+    let profile = Path::new(TMP).join("cli-profile.json");
+    std::fs::write(
+        &first,
+        "(datatype Math (Num i64)) (rewrite (Num 1) (Num 2)) (run 3)",
+    )
+    .unwrap();
+    std::fs::write(&second, "(let two (Num 2))").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_egg-smol"))
+        .args(["--quiet", "--shared", "--profile"])
+        .args([&profile, &first, &second])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let json: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&profile).unwrap()).unwrap();
+    let iterations = |i: usize| json[i]["iterations"].as_array().unwrap().len();
+    assert!(iterations(0) > 0);
+    assert_eq!(iterations(1), 0);
+    assert_eq!(json[1]["rulesets"][0]["rules"][0]["matches"], 0);
+}
+
+#[test]
+fn export_json_writes_the_documented_schema() {
+    let file = Path::new(TMP).join("cli-export.json");
+    let _ = std::fs::remove_file(&file);
+    let output = run(
+        "export-json",
+        &["--quiet", "-F", TMP],
+        r#"(datatype Math (Num i64) (Add Math Math :cost 3))
+           (rewrite (Add a b) (Add b a))
+           (let e (Add (Num 1) (Num 2)))
+           (run 1)
+           (export-json "cli-export.json")"#,
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let json: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&file).unwrap()).unwrap();
+    let object = json.as_object().unwrap();
+    let mut keys: Vec<&String> = object.keys().collect();
+    keys.sort();
+    assert_eq!(keys, ["classes", "nodes", "sorts"]);
+
+    let sorts = json["sorts"].as_array().unwrap();
+    let eq_sort = |name: &str| {
+        let sort = sorts.iter().find(|sort| sort["name"] == name).unwrap();
+        sort["eq_sort"].as_bool().unwrap()
+    };
+    assert!(eq_sort("Math"));
+    assert!(!eq_sort("i64"));
+
+    let nodes = json["nodes"].as_array().unwrap();
+    let classes = json["classes"].as_array().unwrap();
+    let class_ids: Vec<&serde_json::Value> = classes.iter().map(|class| &class["id"]).collect();
+    let check_child = |child: &serde_json::Value| match child.get("class") {
+        Some(class) => assert!(class_ids.contains(&class), "no class {class}"),
+        None => {
+            assert!(child["sort"].is_string(), "{child}");
+            assert!(child["value"].is_string(), "{child}");
+        }
+    };
+    for node in nodes {
+        assert!(node["function"].is_string(), "{node}");
+        assert!(node["cost"].is_u64(), "{node}");
+        node["children"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .for_each(check_child);
+        check_child(&node["output"]);
+    }
+    assert!(nodes.iter().all(|node| node["function"] != "e"));
+
+    for class in classes {
+        assert_eq!(class["sort"], "Math");
+        for index in class["nodes"].as_array().unwrap() {
+            let node = &nodes[index.as_u64().unwrap() as usize];
+            assert_eq!(node["output"]["class"], class["id"], "{node}");
+        }
+    }
+    // the class of `e` holds both orders of the addition
+    let adds: Vec<&serde_json::Value> = nodes
+        .iter()
+        .filter(|node| node["function"] == "Add")
+        .collect();
+    assert_eq!(adds.len(), 2);
+    assert_eq!(adds[0]["output"], adds[1]["output"]);
+    assert_eq!(adds[0]["cost"], 3);
+}
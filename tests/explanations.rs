@@ -0,0 +1,70 @@
+use egg_smol::{ast::Expr, *};
+
+fn num(n: i64) -> Expr {
+    Expr::call("Num", [Expr::lit(n)])
+}
+
+fn add(a: Expr, b: Expr) -> Expr {
+    Expr::call("Add", [a, b])
+}
+
+/// Check that `explanation` is a chain from `from` to `to`.
+fn assert_chain(explanation: &Explanation, from: &Expr, to: &Expr) {
+    assert_eq!(&explanation.start, from, "{explanation}");
+    let last = explanation.steps.last().map(|(_, term)| term);
+    assert_eq!(last, Some(to), "{explanation}");
+}
+
+#[test]
+fn explanations_follow_rules_actions_and_push_pop() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            r#"
+            (set-option enable_explanations 1)
+            (datatype Math (Num i64) (Add Math Math))
+            (rewrite (Add a b) (Add b a))
+            (rewrite (Add (Num a) (Num b)) (Num (+ a b)))
+            (let e (Add (Num 1) (Add (Num 2) (Num 3))))
+            (let seven (Num 7))
+            (run 3)"#,
+        )
+        .unwrap();
+
+    // rule firings
+    let e = add(num(1), add(num(2), num(3)));
+    let by_rules = egraph.explain_equivalence(&e, &num(6)).unwrap();
+    assert_chain(&by_rules, &e, &num(6));
+    assert!(by_rules
+        .steps
+        .iter()
+        .any(|(justification, _)| matches!(justification, Justification::Rule(..))));
+    let folded = egraph
+        .explain_equivalence(&add(num(2), num(3)), &num(5))
+        .unwrap();
+    assert_chain(&folded, &add(num(2), num(3)), &num(5));
+    assert!(folded
+        .steps
+        .iter()
+        .all(|(justification, _)| matches!(justification, Justification::Rule(..))));
+
+    // unions inside a push are explained until the pop, even between
+    // terms that outlive it
+    egraph
+        .parse_and_run_program("(push) (union (Num 6) (Num 7))")
+        .unwrap();
+    for from in [num(6), e.clone()] {
+        let explanation = egraph.explain_equivalence(&from, &num(7)).unwrap();
+        assert_chain(&explanation, &from, &num(7));
+        let (justification, _) = explanation.steps.last().unwrap();
+        assert_eq!(justification, &Justification::Action, "{explanation}");
+    }
+
+    egraph.parse_and_run_program("(pop)").unwrap();
+    egraph
+        .parse_and_run_program("(check (= seven (Num 7))) (check (= e (Num 6)))")
+        .unwrap();
+    assert!(egraph.explain_equivalence(&num(6), &num(7)).is_err());
+    assert!(egraph.explain_equivalence(&e, &num(7)).is_err());
+    assert_eq!(egraph.explain_equivalence(&e, &num(6)).unwrap(), by_rules);
+}
@@ -0,0 +1,306 @@
+use egg_smol::*;
+
+/// Run `program` and return the report of its last `extract`.
+fn extract(program: &str) -> ExtractReport {
+    let mut egraph = EGraph::default();
+    egraph.parse_and_run_program(program).unwrap();
+    egraph.get_extract_report().clone().unwrap()
+}
+
+fn let_string(report: &ExtractReport) -> String {
+    let bindings: Vec<String> = report
+        .bindings
+        .iter()
+        .map(|(name, expr)| format!("({name} {expr})"))
+        .collect();
+    format!("(let ({}) {})", bindings.join(" "), report.expr)
+}
+
+const SHARED: &str = r#"
+(datatype Math
+  (Num i64)
+  (Var String)
+  (Add Math Math)
+  (Mul Math Math)
+  (Neg Math :cost 8))
+
+(let shared (Mul (Var "x") (Var "y")))
+(let e (Add shared shared))
+(union e (Neg (Num 7)))
+"#;
+
+#[test]
+fn tree_cost_counts_shared_subterms_twice() {
+    let report = extract(&format!("{SHARED} (extract e)"));
+    assert_eq!(report.cost, 10);
+    assert_eq!(report.expr.to_string(), "(Neg (Num 7))");
+}
+
+#[test]
+fn dag_cost_counts_shared_subterms_once() {
+    let report = extract(&format!("{SHARED} (extract :dag e)"));
+    assert_eq!(report.cost, 6);
+    assert_eq!(
+        let_string(&report),
+        r#"(let ((v0 (Mul (Var "x") (Var "y")))) (Add v0 v0))"#
+    );
+
+    let report = extract(&format!("{SHARED} (extract :dag (Num 7))"));
+    assert_eq!(report.cost, 2);
+    assert!(report.bindings.is_empty());
+}
+
+#[test]
+fn primitive_outputs_are_not_eclasses() {
+    // the rows of `size` have outputs that collide with the e-class ids of
+    // `x`, but they must never be extracted for it
+    let report = extract(
+        r#"
+        (datatype Math (Num i64) (Var String :cost 10))
+        (function size (Math) i64)
+        (let x (Var "x"))
+        (set (size (Num 0)) 0)
+        (set (size (Num 1)) 1)
+        (set (size (Num 2)) 2)
+        (set (size (Num 3)) 3)
+        (extract x)"#,
+    );
+    assert_eq!(report.cost, 11);
+    assert_eq!(report.expr.to_string(), r#"(Var "x")"#);
+}
+
+/// Extract `root` with the costs cached by earlier extractions, then again
+/// from scratch, and check that both agree.
+fn assert_cache_is_fresh(egraph: &mut EGraph, root: &str) {
+    egraph
+        .parse_and_run_program(&format!("(extract {root})"))
+        .unwrap();
+    let cached = egraph.get_extract_report().clone().unwrap();
+    // a new cost model drops the cache
+    egraph.set_cost_model(DefaultCostModel);
+    egraph
+        .parse_and_run_program(&format!("(extract {root})"))
+        .unwrap();
+    let fresh = egraph.get_extract_report().clone().unwrap();
+    assert_eq!(
+        cached.cost, fresh.cost,
+        "cached {} vs fresh {}",
+        cached.expr, fresh.expr
+    );
+    egraph
+        .parse_and_run_program(&format!("(check (= {root} {}))", cached.expr))
+        .unwrap();
+}
+
+#[test]
+fn cached_extraction_matches_fresh_extraction() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            r#"
+            (datatype Math
+              (Num i64)
+              (Var String)
+              (Add Math Math)
+              (Mul Math Math :cost 4))
+            (define e (Add (Mul (Var "x") (Num 2)) (Num 0)))"#,
+        )
+        .unwrap();
+    assert_cache_is_fresh(&mut egraph, "e");
+
+    let steps = [
+        // new e-nodes in new e-classes
+        (r#"(define f (Add e (Var "y")))"#, "f"),
+        // a new, cheaper e-node for an existing e-class
+        ("(function Two () Math :cost 0) (set (Two) (Num 2))", "f"),
+        // unions
+        ("(rewrite (Mul a (Num 2)) (Add a a)) (run 1)", "f"),
+        // removals
+        (r#"(delete (Add e (Var "y")))"#, "e"),
+        // a union that only lasts until the pop
+        ("(push) (union e (Num 9))", "e"),
+        ("(pop)", "e"),
+    ];
+    for (step, root) in steps {
+        egraph.parse_and_run_program(step).unwrap();
+        assert_cache_is_fresh(&mut egraph, root);
+    }
+}
+
+const SPLIT: &str = r#"
+(datatype T
+  (Big :cost 5)
+  (Leaf1 :cost 4)
+  (Leaf2 :cost 4)
+  (G T)
+  (H T)
+  (F T T))
+
+(let a (G (Big)))
+(union a (Leaf1))
+(let b (H (Big)))
+(union b (Leaf2))
+(let r (F a b))
+"#;
+
+#[test]
+fn exact_finds_sharing_greedy_misses() {
+    let report = extract(&format!("{SPLIT} (extract :dag r)"));
+    assert_eq!(report.cost, 9);
+    assert_eq!(report.expr.to_string(), "(F (Leaf1) (Leaf2))");
+    assert!(!report.optimal);
+
+    let report = extract(&format!("{SPLIT} (extract :exact r)"));
+    assert_eq!(report.cost, 8);
+    assert_eq!(let_string(&report), "(let ((v0 (Big))) (F (G v0) (H v0)))");
+    assert!(report.optimal);
+}
+
+#[test]
+fn exact_out_of_time_falls_back_to_greedy() {
+    let report = extract(&format!("{SPLIT} (extract :exact :time-limit 0 r)"));
+    assert_eq!(report.cost, 9);
+    assert!(!report.optimal);
+}
+
+/// Makes the leaves so expensive that any term containing one saturates.
+struct CostlyLeaves;
+
+impl CostModel for CostlyLeaves {
+    fn node_cost(&self, decl: &ast::FunctionDecl, _: &[Value], _: &[Cost]) -> Cost {
+        if decl.name.as_str().starts_with("Leaf") {
+            Cost::MAX
+        } else {
+            1
+        }
+    }
+}
+
+#[test]
+fn exact_costs_saturate_instead_of_wrapping() {
+    let mut egraph = EGraph::default();
+    egraph.set_cost_model(CostlyLeaves);
+    egraph
+        .parse_and_run_program(&format!("{SPLIT} (extract :exact r)"))
+        .unwrap();
+    let report = egraph.get_extract_report().as_ref().unwrap();
+    assert_eq!(report.cost, 4);
+    assert_eq!(let_string(report), "(let ((v0 (Big))) (F (G v0) (H v0)))");
+}
+
+/// Costs a term by its number of children, ignoring their costs.
+struct Arity;
+
+impl CostModel for Arity {
+    fn node_cost(&self, _decl: &ast::FunctionDecl, inputs: &[Value], _: &[Cost]) -> Cost {
+        inputs.len()
+    }
+}
+
+#[test]
+fn tree_is_optimal_only_under_monotone_cost_models() {
+    let report = extract(&format!("{SHARED} (extract e)"));
+    assert!(report.optimal);
+
+    let mut egraph = EGraph::default();
+    egraph.set_cost_model(Arity);
+    egraph
+        .parse_and_run_program(&format!("{SHARED} (extract e)"))
+        .unwrap();
+    assert!(!egraph.get_extract_report().as_ref().unwrap().optimal);
+}
+
+const VARIANTS: &str = r#"
+(datatype Math
+  (Num i64)
+  (Add Math Math)
+  (Mul Math Math)
+  (Wrap Math :cost 0))
+
+(let two (Num 2))
+(let four (Add two two))
+(union four (Mul two two))
+(union four (Num 4))
+"#;
+
+fn variant_costs(report: &ExtractReport) -> Vec<Cost> {
+    report.variants.iter().map(|(cost, _)| *cost).collect()
+}
+
+#[test]
+fn variants_are_distinct_and_cheapest_first() {
+    let report = extract(&format!("{VARIANTS} (extract :variants 3 four)"));
+    assert_eq!(variant_costs(&report), [2, 5, 5]);
+    assert_eq!(report.variants[0].1.to_string(), "(Num 4)");
+    assert_ne!(report.variants[1].1, report.variants[2].1);
+
+    let report = extract(&format!(
+        "{VARIANTS} (let e (Add four four)) (extract :variants 5 e)"
+    ));
+    assert_eq!(variant_costs(&report), [5, 8, 8, 8, 8]);
+    assert_eq!(report.variants[0].1.to_string(), "(Add (Num 4) (Num 4))");
+}
+
+#[test]
+fn variants_terminate_on_zero_cost_cycles() {
+    let report = extract(&format!(
+        "{VARIANTS} (union two (Wrap two)) (extract :variants 3 two)"
+    ));
+    assert_eq!(variant_costs(&report), [2, 2, 2]);
+    assert_eq!(report.variants[2].1.to_string(), "(Wrap (Wrap (Num 2)))");
+}
+
+/// Makes `Wrap` free no matter what it wraps, so a class containing
+/// `(Wrap x)` prefers it to everything else, including its own child.
+struct FreeWrap;
+
+impl CostModel for FreeWrap {
+    fn node_cost(&self, decl: &ast::FunctionDecl, _: &[Value], _: &[Cost]) -> Cost {
+        usize::from(decl.name.as_str() != "Wrap")
+    }
+}
+
+#[test]
+fn cyclic_best_terms_are_an_error() {
+    let mut egraph = EGraph::default();
+    egraph.set_cost_model(FreeWrap);
+    let err = egraph
+        .parse_and_run_program(&format!("{VARIANTS} (union two (Wrap two)) (extract two)"))
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("contains itself"),
+        "unexpected error: {err}"
+    );
+}
+
+const HIDDEN: &str = r#"
+(datatype Math
+  (Num i64)
+  (Add Math Math))
+
+;; the cheapest term if it were extractable
+(function Double (Math) Math :cost 0 :unextractable)
+(rewrite (Double x) (Add x x))
+
+(let d (Double (Num 2)))
+(run 1)
+"#;
+
+#[test]
+fn unextractable_functions_are_never_extracted() {
+    let expected = "(Add (Num 2) (Num 2))";
+    let report = extract(&format!("{HIDDEN} (extract :variants 2 d)"));
+    assert_eq!(report.cost, 5);
+    assert_eq!(report.expr.to_string(), expected);
+    assert_eq!(report.variants[0].1.to_string(), expected);
+    assert!(report
+        .variants
+        .iter()
+        .all(|(_, expr)| !expr.to_string().contains("Double")));
+
+    let report = extract(&format!("{HIDDEN} (extract :dag d)"));
+    assert_eq!(let_string(&report), "(let ((v0 (Num 2))) (Add v0 v0))");
+
+    let report = extract(&format!("{HIDDEN} (extract :exact d)"));
+    assert!(!let_string(&report).contains("Double"));
+}
@@ -1,3 +1,5 @@
+use std::path::{Path, PathBuf};
+
 use egg_smol::{
     ast::{Command, Expr, Literal},
     *,
@@ -13,14 +15,14 @@ impl Run {
     fn run(&self) {
         let _ = env_logger::builder().is_test(true).try_init();
         let program = std::fs::read_to_string(self.path).unwrap();
-        self.test_program(&program, "Top level error");
+        let fact_directory = self.fact_directory();
+        self.test_program(&program, &fact_directory, "Top level error");
 
         if !self.should_fail {
             let mut egraph = EGraph::default();
             egraph.set_underscores_for_desugaring(4);
-            let parsed = egraph.parse_program(&program).unwrap();
             let desugared_str = egraph
-                .process_commands(parsed)
+                .parse_and_process_program_with_filename(self.path.into(), &program)
                 .unwrap()
                 .into_iter()
                 .map(|x| x.to_string())
@@ -31,6 +33,7 @@ impl Run {
 
             self.test_program(
                 &desugared_str,
+                &fact_directory,
                 &format!(
                     "Program:\n{}\n ERROR after parse, to_string, and parse again.",
                     desugared_str
@@ -39,9 +42,34 @@ impl Run {
         }
     }
 
-    fn test_program(&self, program: &str, message: &str) {
+    /// A fresh directory for the files the test writes, so that tests running
+    /// at the same time never share them. The data files under `tests/` are
+    /// copied to the same place in it.
+    fn fact_directory(&self) -> PathBuf {
+        let name = Path::new(self.path).file_stem().unwrap().to_string_lossy();
+        let mode = if self.test_proofs {
+            "with_proofs"
+        } else {
+            "plain"
+        };
+        let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{name}-{mode}"));
+        let _ = std::fs::remove_dir_all(&dir);
+        let data = dir.join("tests");
+        std::fs::create_dir_all(&data).unwrap();
+        let tests = std::fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/tests")).unwrap();
+        for entry in tests {
+            let path = entry.unwrap().path();
+            if path.extension().map_or(false, |ext| ext == "csv") {
+                std::fs::copy(&path, data.join(path.file_name().unwrap())).unwrap();
+            }
+        }
+        dir
+    }
+
+    fn test_program(&self, program: &str, fact_directory: &Path, message: &str) {
         let mut egraph = EGraph::default();
         egraph.set_underscores_for_desugaring(5);
+        egraph.fact_directory = Some(fact_directory.into());
         if self.test_proofs {
             egraph
                 .run_program(vec![Command::SetOption {
@@ -51,7 +79,7 @@ impl Run {
                 .unwrap();
             egraph.test_proofs = true;
         }
-        match egraph.parse_and_run_program(program) {
+        match egraph.parse_and_run_program_with_filename(self.path.into(), program) {
             Ok(msgs) => {
                 if self.should_fail {
                     panic!(
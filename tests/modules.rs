@@ -0,0 +1,25 @@
+use egg_smol::*;
+
+#[test]
+fn only_exported_names_are_visible_outside_a_module() {
+    let module = "(module arith
+                    (export Num)
+                    (datatype Math (Num i64))
+                    (function eval (Math) i64)
+                    (set (eval (Num 1)) 1))";
+    let mut egraph = EGraph::default();
+    egraph.parse_and_run_program(module).unwrap();
+    egraph
+        .parse_and_run_program("(let one (arith.Num 1))")
+        .unwrap();
+    let err = egraph
+        .parse_and_run_program("(check (= (arith.eval one) 1))")
+        .unwrap_err();
+    let Error::Spanned(_, err) = &err else {
+        panic!("no span for {err}")
+    };
+    assert!(
+        matches!(**err, Error::NotExported(name, module) if name == "arith.eval".into() && module == "arith".into()),
+        "{err}"
+    );
+}
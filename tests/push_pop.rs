@@ -0,0 +1,37 @@
+use egg_smol::*;
+
+#[test]
+fn push_and_pop_many_times_on_a_long_program() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            "(datatype Math (Num i64) (Add Math Math))
+             (rewrite (Add (Num a) (Num b)) (Num (+ a b)))",
+        )
+        .unwrap();
+    for i in 0..500 {
+        egraph
+            .parse_and_run_program(&format!("(simplify 1 (Add (Num {i}) (Num 1)))"))
+            .unwrap();
+    }
+
+    // the commands run between a push and its pop leave nothing behind, only
+    // the push itself does
+    let local_types = egraph.type_info().local_types.len();
+    for i in 0..500 {
+        egraph
+            .parse_and_run_program(&format!(
+                "(push)
+                 (let e (Add (Num {i}) (Num 1)))
+                 (run 1)
+                 (check (= e (Num {})))
+                 (pop)",
+                i + 1
+            ))
+            .unwrap();
+        assert_eq!(egraph.type_info().local_types.len(), local_types + i + 1);
+    }
+    egraph
+        .parse_and_run_program("(fail (check (Num 500))) (let e (Num 0)) (check (= e (Num 0)))")
+        .unwrap();
+}
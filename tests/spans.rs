@@ -0,0 +1,49 @@
+use egg_smol::{ast::Span, *};
+
+/// The span `err` points at, and the error it wraps.
+fn spanned(err: &Error) -> (&Span, &Error) {
+    match err {
+        Error::Spanned(span, inner) => (span, inner),
+        _ => panic!("no span for {err}"),
+    }
+}
+
+#[test]
+fn errors_point_at_the_failing_command() {
+    let mut egraph = EGraph::default();
+    let program = "(datatype Math (Num i64))\n\n  (check (= (Num 1)\n            (Num 2)))";
+    let err = egraph
+        .parse_and_run_program_with_filename("test.egg".into(), program)
+        .unwrap_err();
+    let (span, _) = spanned(&err);
+    assert_eq!(span.to_string(), "test.egg:3:3");
+    assert_eq!(span.text(), "(check (= (Num 1)\n            (Num 2)))");
+}
+
+#[test]
+fn parse_errors_point_at_the_unexpected_token() {
+    let egraph = EGraph::default();
+    let err = egraph.parse_program("(check (Num 1)").unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("<input>:1:15: Unexpected end of input"));
+
+    // errors in literals point at the literal
+    let err = egraph
+        .parse_program("(relation Str (String))\n(Str \"a\\qb\")")
+        .unwrap_err();
+    let (span, _) = spanned(&err);
+    assert_eq!(span.to_string(), "<input>:2:6");
+    assert_eq!(span.text(), "\"a\\qb\"");
+    assert!(err.to_string().contains("Unknown escape \\q"), "{err}");
+}
+
+#[test]
+fn errors_in_modules_point_at_the_body_command() {
+    let mut egraph = EGraph::default();
+    let program = "(module m\n  (datatype Math (Num i64))\n  (check (= (Num 1) (Num 2))))";
+    let err = egraph.parse_and_run_program(program).unwrap_err();
+    let (span, _) = spanned(&err);
+    assert_eq!(span.to_string(), "<input>:3:3");
+    assert_eq!(span.text(), "(check (= (Num 1) (Num 2)))");
+}